@@ -0,0 +1,445 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: Sergio Martins
+
+use crate::output;
+use rocketchat::{Message, Paging, RocketChat, Room};
+use std::{collections::VecDeque, io::Write, path::Path, sync::Arc};
+
+/// How many printed messages tail remembers, to skip the edits and reactions to them
+const TAIL_SEEN: usize = 1000;
+
+/// Where results go, as text or as JSON
+pub struct Output<W: Write> {
+    pub json: bool,
+    pub out: W,
+}
+
+impl<W: Write> Output<W> {
+    fn write(&mut self, text: &str, json: serde_json::Value) -> Result<(), String> {
+        let result = if self.json {
+            writeln!(self.out, "{}", json)
+        } else {
+            writeln!(self.out, "{}", text)
+        };
+        result.map_err(|e| e.to_string())
+    }
+
+    fn messages(&mut self, messages: &[Message]) -> Result<(), String> {
+        if self.json {
+            let json: Vec<_> = messages.iter().map(output::message_json).collect();
+            return self.write("", serde_json::json!(json));
+        }
+        for message in messages {
+            self.write(&output::message_line(message), serde_json::Value::Null)?;
+        }
+        Ok(())
+    }
+}
+
+/// Logs in with the token saved by `login`
+pub async fn resume(rc: &RocketChat) -> Result<(), String> {
+    if rc.login_via_saved_token().await? {
+        Ok(())
+    } else {
+        Err(String::from(
+            "not logged in, run `rocketchat-cli login` first",
+        ))
+    }
+}
+
+/// Finds a room by ID or by name, with or without the leading #
+pub fn find_room(rc: &RocketChat, room: &str) -> Result<Arc<Room>, String> {
+    if let Some(found) = rc.find_room(room) {
+        return Ok(found);
+    }
+
+    let name = room.trim_start_matches('#');
+    rc.rooms()
+        .values()
+        .find(|r| r.display_name() == name)
+        .cloned()
+        .ok_or(format!("no room called {}", room))
+}
+
+pub async fn login<W: Write>(
+    rc: &RocketChat,
+    output: &mut Output<W>,
+    user: &str,
+    password: &str,
+) -> Result<(), String> {
+    rc.login(user, password).await?;
+    output.write(
+        &format!("Logged in as {}", rc.get_username()),
+        serde_json::json!({ "user_id": rc.get_user_id(), "username": rc.get_username() }),
+    )
+}
+
+pub async fn whoami<W: Write>(rc: &RocketChat, output: &mut Output<W>) -> Result<(), String> {
+    resume(rc).await?;
+    rc.load_profile().await?;
+    output.write(
+        &format!("{} ({})", rc.get_username(), rc.get_user_id()),
+        serde_json::json!({ "user_id": rc.get_user_id(), "username": rc.get_username() }),
+    )
+}
+
+pub async fn rooms<W: Write>(rc: &RocketChat, output: &mut Output<W>) -> Result<(), String> {
+    resume(rc).await?;
+    rc.list_rooms().await?;
+    rc.list_subscriptions().await?;
+
+    let rooms = rc.rooms();
+    let mut rooms: Vec<&Room> = rooms.values().map(|r| r.as_ref()).collect();
+    rooms.sort_by_key(|r| r.display_name());
+
+    let unread = |room: &Room| {
+        let unread = rc.subscription(room.id()).map(|s| s.unread).unwrap_or(0);
+        (unread, rc.mention_counters(room.id()).total())
+    };
+
+    if output.json {
+        let json: Vec<_> = rooms
+            .iter()
+            .map(|room| {
+                let (unread, mentions) = unread(room);
+                output::room_json(room, unread, mentions)
+            })
+            .collect();
+        return output.write("", serde_json::json!(json));
+    }
+
+    for room in rooms {
+        let (unread, mentions) = unread(room);
+        output.write(
+            &output::room_line(room, unread, mentions),
+            serde_json::Value::Null,
+        )?;
+    }
+    Ok(())
+}
+
+pub async fn history<W: Write>(
+    rc: &RocketChat,
+    output: &mut Output<W>,
+    room: &str,
+    count: u64,
+) -> Result<(), String> {
+    resume(rc).await?;
+    rc.list_rooms().await?;
+    let room = find_room(rc, room)?;
+
+    let mut messages = rc
+        .history(room.id(), Paging { offset: 0, count })
+        .await?
+        .messages;
+    messages.reverse();
+    output.messages(&messages)
+}
+
+pub async fn send<W: Write>(
+    rc: &RocketChat,
+    output: &mut Output<W>,
+    room: &str,
+    text: &str,
+) -> Result<(), String> {
+    resume(rc).await?;
+    rc.list_rooms().await?;
+    let room = find_room(rc, room)?;
+
+    let message = rc.send_message(room.id(), text).await?;
+    output.write(
+        &format!("Sent {}", message.id),
+        output::message_json(&message),
+    )
+}
+
+pub async fn upload<W: Write>(
+    rc: &RocketChat,
+    output: &mut Output<W>,
+    room: &str,
+    file: &Path,
+    description: Option<&str>,
+) -> Result<(), String> {
+    resume(rc).await?;
+    rc.list_rooms().await?;
+    let room = find_room(rc, room)?;
+
+    let message = rc.upload(room.id(), file, description).await?;
+    output.write(
+        &format!("Uploaded {}", file.display()),
+        output::message_json(&message),
+    )
+}
+
+pub async fn search<W: Write>(
+    rc: &RocketChat,
+    output: &mut Output<W>,
+    query: &str,
+    room: Option<&str>,
+) -> Result<(), String> {
+    resume(rc).await?;
+    rc.list_rooms().await?;
+
+    let results = match room {
+        Some(room) => {
+            let room = find_room(rc, room)?;
+            rc.search_messages(room.id(), query, Paging::default())
+                .await?
+        }
+        None => rc.search_all_messages(query, Paging::default()).await?,
+    };
+
+    if output.json {
+        let json: Vec<_> = results
+            .iter()
+            .map(|r| {
+                let mut json = output::message_json(&r.message);
+                json["room"] = serde_json::json!(r.room.display_name());
+                json
+            })
+            .collect();
+        return output.write("", serde_json::json!(json));
+    }
+
+    for result in results {
+        output.write(
+            &format!(
+                "#{} {}",
+                result.room.display_name(),
+                output::message_line(&result.message)
+            ),
+            serde_json::Value::Null,
+        )?;
+    }
+    Ok(())
+}
+
+/// Prints a message unless it was printed already, remembering the latest TAIL_SEEN
+fn print_new<W: Write>(
+    output: &mut Output<W>,
+    seen: &mut VecDeque<String>,
+    message: &Message,
+) -> Result<(), String> {
+    if seen.contains(&message.id) {
+        return Ok(());
+    }
+    if seen.len() == TAIL_SEEN {
+        seen.pop_front();
+    }
+    seen.push_back(message.id.clone());
+
+    output.write(
+        &output::message_line(message),
+        output::message_json(message),
+    )?;
+    output.out.flush().map_err(|e| e.to_string())
+}
+
+/// Prints the latest messages, then new ones as they arrive, until interrupted
+/// With JSON output, each message is printed on its own line.
+pub async fn tail<W: Write>(
+    rc: &RocketChat,
+    output: &mut Output<W>,
+    room: &str,
+) -> Result<(), String> {
+    resume(rc).await?;
+    rc.list_rooms().await?;
+    let room = find_room(rc, room)?;
+
+    // Subscribed before fetching the history, so nothing falls in between
+    let mut realtime = rc.realtime().await?;
+    realtime.subscribe_room_messages(room.id()).await?;
+
+    let mut seen = VecDeque::new();
+    let mut messages = rc
+        .history(
+            room.id(),
+            Paging {
+                offset: 0,
+                count: 10,
+            },
+        )
+        .await?
+        .messages;
+    messages.reverse();
+    for message in &messages {
+        print_new(output, &mut seen, message)?;
+    }
+
+    loop {
+        let message = tokio::select! {
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+            message = realtime.next_message() => message?,
+        };
+        match message {
+            Some(message) => print_new(output, &mut seen, &message)?,
+            None => return Err(String::from("lost the connection to the server")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_server::FakeServer;
+
+    fn message_json(id: &str, text: &str) -> serde_json::Value {
+        serde_json::json!({
+            "_id": id,
+            "rid": "GENERAL",
+            "msg": text,
+            "ts": "2022-05-17T14:55:23.276Z",
+            "u": { "_id": "u2", "username": "bob" }
+        })
+    }
+
+    /// A server with one channel, that accepts alice's password
+    async fn fake_server() -> FakeServer {
+        let server = FakeServer::start().await;
+        server.respond(
+            "api/v1/login",
+            serde_json::json!({
+                "status": "success",
+                "data": { "userId": "u1", "authToken": "t1", "me": { "username": "alice" } }
+            }),
+        );
+        server.respond(
+            "api/v1/rooms.get",
+            serde_json::json!({
+                "success": true,
+                "update": [{ "_id": "GENERAL", "t": "c", "name": "general" }]
+            }),
+        );
+        server.respond(
+            "api/v1/subscriptions.get",
+            serde_json::json!({
+                "success": true,
+                "update": [{ "_id": "s1", "rid": "GENERAL", "t": "c", "name": "general", "unread": 2 }]
+            }),
+        );
+        server.respond(
+            "api/v1/channels.history",
+            serde_json::json!({
+                "success": true,
+                "messages": [message_json("m2", "second"), message_json("m1", "first")]
+            }),
+        );
+        server
+    }
+
+    fn client(server: &FakeServer, dir: &tempfile::TempDir) -> RocketChat {
+        RocketChat::new(&server.url, rocketchat::Paths::in_dir(dir.path()))
+    }
+
+    fn text_output() -> Output<Vec<u8>> {
+        Output {
+            json: false,
+            out: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_commands() {
+        let server = fake_server().await;
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut output = text_output();
+        login(&client(&server, &dir), &mut output, "alice", "secret")
+            .await
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output.out).unwrap(),
+            "Logged in as alice\n"
+        );
+        assert_eq!(
+            server.requests("api/v1/login").last().unwrap().body,
+            serde_json::json!({ "user": "alice", "password": "secret" })
+        );
+
+        // Later commands resume with the saved token
+        let rc = client(&server, &dir);
+        let mut output = text_output();
+        rooms(&rc, &mut output).await.unwrap();
+        assert_eq!(
+            String::from_utf8(output.out).unwrap(),
+            "GENERAL  general  (2 unread)\n"
+        );
+        assert_eq!(
+            server.requests("api/v1/login").last().unwrap().body,
+            serde_json::json!({ "resume": "t1" })
+        );
+
+        let mut output = text_output();
+        history(&rc, &mut output, "#general", 2).await.unwrap();
+        let printed = String::from_utf8(output.out).unwrap();
+        let lines: Vec<&str> = printed.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with("bob: first"));
+
+        server.respond(
+            "api/v1/chat.sendMessage",
+            serde_json::json!({ "success": true, "message": message_json("m3", "hello") }),
+        );
+        let mut output = Output {
+            json: true,
+            out: Vec::new(),
+        };
+        send(&rc, &mut output, "general", "hello").await.unwrap();
+        let sent: serde_json::Value = serde_json::from_slice(&output.out).unwrap();
+        assert_eq!(sent["id"], "m3");
+        let request = &server.requests("api/v1/chat.sendMessage")[0];
+        assert_eq!(request.method, "POST");
+        assert_eq!(
+            request.body["message"],
+            serde_json::json!({ "rid": "GENERAL", "msg": "hello" })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tail() {
+        let server = fake_server().await;
+        // m2 is in the history too, then gets edited, so it's printed only once
+        server.stream(message_json("m2", "second"));
+        server.stream(message_json("m3", "third"));
+        let dir = tempfile::tempdir().unwrap();
+        let rc = client(&server, &dir);
+        rc.login("alice", "secret").await.unwrap();
+
+        let mut output = text_output();
+        let result = tail(&rc, &mut output, "general").await;
+        assert_eq!(
+            result,
+            Err(String::from("lost the connection to the server"))
+        );
+
+        let printed = String::from_utf8(output.out).unwrap();
+        let texts: Vec<&str> = printed
+            .lines()
+            .map(|line| line.rsplit(": ").next().unwrap())
+            .collect();
+        assert_eq!(texts, vec!["first", "second", "third"]);
+    }
+
+    #[test]
+    fn test_find_room() {
+        let dir = tempfile::tempdir().unwrap();
+        let rc = RocketChat::new("https://localhost", rocketchat::Paths::in_dir(dir.path()));
+        rc.set_channel_rooms(vec![rocketchat::Channel::for_test("GENERAL", "general")]);
+
+        assert_eq!(find_room(&rc, "GENERAL").unwrap().id(), "GENERAL");
+        assert_eq!(find_room(&rc, "#general").unwrap().id(), "GENERAL");
+        assert!(find_room(&rc, "random").is_err());
+    }
+
+    #[test]
+    fn test_output() {
+        let mut output = Output {
+            json: true,
+            out: Vec::new(),
+        };
+        output
+            .write("text", serde_json::json!({ "id": "m1" }))
+            .unwrap();
+        assert_eq!(String::from_utf8(output.out).unwrap(), "{\"id\":\"m1\"}\n");
+    }
+}
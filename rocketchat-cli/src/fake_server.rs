@@ -0,0 +1,187 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: Sergio Martins
+
+//! A scripted Rocket.Chat server for the command tests: canned REST responses, and a realtime
+//! websocket that streams a few messages then disconnects
+
+use futures::{SinkExt, StreamExt};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tokio_tungstenite::tungstenite::Message as Frame;
+
+/// A request the server got, with the path stripped of its query
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub body: serde_json::Value,
+}
+
+#[derive(Default)]
+struct Script {
+    /// By path, without the leading slash
+    responses: HashMap<String, serde_json::Value>,
+    /// What the realtime subscription delivers, as message objects
+    streamed: Vec<serde_json::Value>,
+    requests: Vec<Request>,
+}
+
+#[derive(Clone)]
+pub struct FakeServer {
+    pub url: String,
+    script: Arc<Mutex<Script>>,
+}
+
+impl FakeServer {
+    pub async fn start() -> FakeServer {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = FakeServer {
+            url: format!("http://{}", listener.local_addr().unwrap()),
+            script: Arc::default(),
+        };
+
+        let script = server.script.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, script.clone()));
+            }
+        });
+        server
+    }
+
+    /// Answers requests to `path`, for example "api/v1/rooms.get", with `body`
+    pub fn respond(&self, path: &str, body: serde_json::Value) {
+        let mut script = self.script.lock().unwrap();
+        script.responses.insert(String::from(path), body);
+    }
+
+    pub fn stream(&self, message: serde_json::Value) {
+        self.script.lock().unwrap().streamed.push(message);
+    }
+
+    pub fn requests(&self, path: &str) -> Vec<Request> {
+        let script = self.script.lock().unwrap();
+        script
+            .requests
+            .iter()
+            .filter(|r| r.path == path)
+            .cloned()
+            .collect()
+    }
+}
+
+async fn serve(stream: TcpStream, script: Arc<Mutex<Script>>) {
+    let mut head = [0; 1024];
+    let peeked = stream.peek(&mut head).await.unwrap_or(0);
+    let head = String::from_utf8_lossy(&head[..peeked]).to_lowercase();
+    if head.contains("upgrade: websocket") {
+        serve_realtime(stream, script).await;
+    } else {
+        serve_rest(stream, script).await;
+    }
+}
+
+async fn serve_rest(mut stream: TcpStream, script: Arc<Mutex<Script>>) {
+    let mut request = Vec::new();
+    let mut buffer = [0; 4096];
+    let head_end = loop {
+        let read = stream.read(&mut buffer).await.unwrap_or(0);
+        if read == 0 {
+            return;
+        }
+        request.extend_from_slice(&buffer[..read]);
+        if let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end + 4;
+        }
+    };
+
+    let head = String::from_utf8_lossy(&request[..head_end]).to_string();
+    let content_length: usize = head
+        .lines()
+        .find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.eq_ignore_ascii_case("content-length")
+                .then(|| value.trim().parse().ok())?
+        })
+        .unwrap_or(0);
+    while request.len() < head_end + content_length {
+        let read = stream.read(&mut buffer).await.unwrap_or(0);
+        if read == 0 {
+            break;
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+
+    let mut request_line = head.split_whitespace();
+    let method = String::from(request_line.next().unwrap_or_default());
+    let target = request_line.next().unwrap_or_default();
+    let path = String::from(
+        target
+            .split('?')
+            .next()
+            .unwrap_or_default()
+            .strip_prefix('/')
+            .unwrap_or_default(),
+    );
+    let body = serde_json::from_slice(&request[head_end..]).unwrap_or_default();
+
+    let response = {
+        let mut script = script.lock().unwrap();
+        script.requests.push(Request {
+            method,
+            path: path.clone(),
+            body,
+        });
+        script.responses.get(&path).cloned()
+    };
+    let (status, body) = match response {
+        Some(body) => ("200 OK", body.to_string()),
+        None => ("404 Not Found", String::from(r#"{"success": false}"#)),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+}
+
+/// Plays along with connect, login and sub, then streams the scripted messages and hangs up
+async fn serve_realtime(stream: TcpStream, script: Arc<Mutex<Script>>) {
+    let Ok(mut socket) = tokio_tungstenite::accept_async(stream).await else {
+        return;
+    };
+
+    while let Some(Ok(Frame::Text(text))) = socket.next().await {
+        let message: serde_json::Value = serde_json::from_str(&text).unwrap_or_default();
+        let id = message["id"].clone();
+        let reply = match message["msg"].as_str() {
+            Some("connect") => serde_json::json!({ "msg": "connected", "session": "s1" }),
+            Some("method") => serde_json::json!({ "msg": "result", "id": id }),
+            Some("sub") => {
+                let ready = serde_json::json!({ "msg": "ready", "subs": [id] });
+                let _ = socket.send(Frame::Text(ready.to_string())).await;
+                let streamed = script.lock().unwrap().streamed.clone();
+                for message in streamed {
+                    let changed = serde_json::json!({
+                        "msg": "changed",
+                        "collection": "stream-room-messages",
+                        "fields": { "eventName": message["rid"], "args": [message] },
+                    });
+                    let _ = socket.send(Frame::Text(changed.to_string())).await;
+                }
+                let _ = socket.close(None).await;
+                return;
+            }
+            _ => continue,
+        };
+        let _ = socket.send(Frame::Text(reply.to_string())).await;
+    }
+}
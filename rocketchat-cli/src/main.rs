@@ -0,0 +1,127 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: Sergio Martins
+
+use clap::{Parser, Subcommand};
+use commands::Output;
+use std::{
+    io::{BufRead, IsTerminal},
+    path::PathBuf,
+};
+
+mod commands;
+#[cfg(test)]
+mod fake_server;
+mod output;
+
+/// Rocket.Chat from the terminal
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    /// The server, for example https://open.rocket.chat
+    #[arg(long, env = "RC_SERVER")]
+    server: String,
+
+    /// Print JSON instead of text, for piping into jq
+    #[arg(long, global = true)]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Logs in and saves the token for the other commands
+    Login {
+        user: String,
+        /// Read from stdin if not given
+        #[arg(long, env = "RC_PASSWORD", hide_env_values = true)]
+        password: Option<String>,
+    },
+    /// Shows who we're logged in as
+    Whoami,
+    /// Lists our rooms, with their unread counts
+    Rooms,
+    /// Shows the latest messages of a room
+    History {
+        /// Room name or ID
+        room: String,
+        #[arg(long, default_value_t = 50)]
+        count: u64,
+    },
+    /// Posts a message
+    Send { room: String, message: String },
+    /// Prints new messages as they arrive, until interrupted
+    Tail { room: String },
+    /// Uploads a file to a room
+    Upload {
+        room: String,
+        file: PathBuf,
+        #[arg(long)]
+        description: Option<String>,
+    },
+    /// Searches messages, in every room unless one is given
+    Search {
+        query: String,
+        #[arg(long)]
+        room: Option<String>,
+    },
+}
+
+/// Prompts without echoing when run from a terminal, otherwise reads a line from stdin
+fn read_password() -> Result<String, String> {
+    if std::io::stdin().is_terminal() {
+        return rpassword::prompt_password("Password: ").map_err(|e| e.to_string());
+    }
+
+    let mut password = String::new();
+    std::io::stdin()
+        .lock()
+        .read_line(&mut password)
+        .map_err(|e| e.to_string())?;
+    Ok(password.trim_end_matches(['\r', '\n']).to_string())
+}
+
+async fn run(cli: Cli) -> Result<(), String> {
+    // Kept apart from the app's, and per server, so each has its own login
+    let server = cli.server.trim_end_matches('/');
+    let paths = rocketchat::Paths::for_app("rocketchat-cli")?.for_server(server);
+    let rc = rocketchat::RocketChat::new(server, paths);
+    let mut output = Output {
+        json: cli.json,
+        out: std::io::stdout().lock(),
+    };
+
+    match cli.command {
+        Command::Login { user, password } => {
+            let password = match password {
+                Some(password) => password,
+                None => read_password()?,
+            };
+            commands::login(&rc, &mut output, &user, &password).await
+        }
+        Command::Whoami => commands::whoami(&rc, &mut output).await,
+        Command::Rooms => commands::rooms(&rc, &mut output).await,
+        Command::History { room, count } => commands::history(&rc, &mut output, &room, count).await,
+        Command::Send { room, message } => commands::send(&rc, &mut output, &room, &message).await,
+        Command::Tail { room } => commands::tail(&rc, &mut output, &room).await,
+        Command::Upload {
+            room,
+            file,
+            description,
+        } => commands::upload(&rc, &mut output, &room, &file, description.as_deref()).await,
+        Command::Search { query, room } => {
+            commands::search(&rc, &mut output, &query, room.as_deref()).await
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+
+    if let Err(e) = run(Cli::parse()).await {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
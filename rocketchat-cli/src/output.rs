@@ -0,0 +1,110 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: Sergio Martins
+
+use chrono::TimeZone;
+use rocketchat::{Message, Room, RoomType};
+
+/// Formats a timestamp, in seconds, in the local timezone
+fn time(timestamp: i64) -> String {
+    chrono::Local
+        .timestamp_opt(timestamp, 0)
+        .single()
+        .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_default()
+}
+
+fn room_type(room_type: RoomType) -> &'static str {
+    match room_type {
+        RoomType::Channel => "channel",
+        RoomType::PrivateGroup => "group",
+        RoomType::Direct => "direct",
+        RoomType::MultiUserDirect => "multi-user direct",
+        RoomType::Discussion { .. } => "discussion",
+        RoomType::TeamMain { .. } => "team",
+        RoomType::Livechat => "livechat",
+        RoomType::Unknown => "unknown",
+    }
+}
+
+pub fn message_json(message: &Message) -> serde_json::Value {
+    serde_json::json!({
+        "id": message.id,
+        "room_id": message.room_id,
+        "author": message.author_username,
+        "timestamp": message.timestamp,
+        "text": message.text,
+        "thread_id": message.thread_id,
+        "pinned": message.pinned,
+    })
+}
+
+pub fn message_line(message: &Message) -> String {
+    format!(
+        "[{}] {}: {}",
+        time(message.timestamp),
+        message.author_username,
+        message.text
+    )
+}
+
+/// `unread` and `mentions` are what the room has waiting for us
+pub fn room_json(room: &Room, unread: u64, mentions: u64) -> serde_json::Value {
+    serde_json::json!({
+        "id": room.id(),
+        "name": room.display_name(),
+        "type": room_type(room.room_type()),
+        "unread": unread,
+        "mentions": mentions,
+    })
+}
+
+pub fn room_line(room: &Room, unread: u64, mentions: u64) -> String {
+    let mut line = format!("{}  {}", room.id(), room.display_name());
+    if unread > 0 {
+        line += &format!("  ({} unread", unread);
+        if mentions > 0 {
+            line += &format!(", {} mentions", mentions);
+        }
+        line += ")";
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message() -> Message {
+        Message::from_json(&serde_json::json!({
+            "_id": "msg1",
+            "rid": "GENERAL",
+            "msg": "hello",
+            "ts": "2022-05-17T14:55:23.276Z",
+            "u": { "_id": "user1", "username": "sergio" }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_message_output() {
+        let message = message();
+        assert!(message_line(&message).ends_with("] sergio: hello"));
+
+        let json = message_json(&message);
+        assert_eq!(json["author"], "sergio");
+        assert_eq!(json["timestamp"], 1652799323);
+        assert_eq!(json["thread_id"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn test_room_output() {
+        let room = Room::Channel(rocketchat::Channel::for_test("GENERAL", "general"));
+
+        assert_eq!(room_line(&room, 0, 0), "GENERAL  general");
+        assert_eq!(
+            room_line(&room, 3, 1),
+            "GENERAL  general  (3 unread, 1 mentions)"
+        );
+        assert_eq!(room_json(&room, 3, 1)["type"], "channel");
+    }
+}
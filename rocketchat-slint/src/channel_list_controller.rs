@@ -3,52 +3,98 @@
 
 use crate::slint_generatedAppWindow::AppWindow;
 use crate::slint_generatedAppWindow::Channel;
+use rocketchat::{Event, LoginState, StepState, SyncProgress};
 use slint::SharedString;
-use std::rc::Rc;
+use std::{cell::RefCell, rc::Rc, sync::Arc};
+use tokio::sync::broadcast::error::RecvError;
 
 pub struct Controller {
     ui: AppWindow,
-    model: Rc<rocketchat::RocketChat>,
+    model: Arc<rocketchat::RocketChat>,
+    sync_progress: RefCell<SyncProgress>,
 }
 
-pub fn on_login_changed(controller: Rc<Controller>) {
-    if controller.model.is_logged_in() {
-        let controller = controller.clone();
+impl Controller {
+    pub fn new(ui: AppWindow, model: Arc<rocketchat::RocketChat>) -> Rc<Self> {
+        let controller = Rc::new(Self {
+            ui,
+            model,
+            sync_progress: RefCell::default(),
+        });
+
+        let weak_controller = Rc::downgrade(&controller);
+        let mut events = controller.model.subscribe();
         slint::spawn_local(async move {
-            controller.load_channel_list().await;
+            loop {
+                let event = events.recv().await;
+                let Some(controller) = weak_controller.upgrade() else {
+                    break;
+                };
+                match event {
+                    // The rooms are fetched by the sync task, and arrive as RoomsReset
+                    Ok(Event::LoginChanged(LoginState::LoggedIn)) => {
+                        *controller.sync_progress.borrow_mut() = SyncProgress::default();
+                    }
+                    Ok(Event::LoginChanged(_)) => controller.clear_channel_list(),
+                    Ok(Event::RoomAdded(_))
+                    | Ok(Event::RoomUpdated(_))
+                    | Ok(Event::RoomRemoved(_))
+                    | Ok(Event::RoomsReset)
+                    // Missed some changes, just show everything again
+                    | Err(RecvError::Lagged(_)) => controller.update_channel_list(),
+                    Ok(Event::SyncProgress(step, state)) => {
+                        controller.update_sync_status(step, state)
+                    }
+                    Ok(_) => {}
+                    Err(RecvError::Closed) => break,
+                }
+            }
         })
         .unwrap();
+
+        controller
     }
-}
 
-impl Controller {
-    pub fn new(ui: AppWindow, model: Rc<rocketchat::RocketChat>) -> Rc<Self> {
-        Rc::new(Self { ui, model })
+    fn clear_channel_list(&self) {
+        let channel_model: Rc<slint::VecModel<Channel>> = Rc::new(slint::VecModel::default());
+        self.ui.set_channelModel(channel_model.into());
+    }
+
+    fn update_sync_status(&self, step: rocketchat::SyncStep, state: StepState) {
+        let mut progress = self.sync_progress.borrow_mut();
+        progress.update(step, state.clone());
+
+        let status = match state {
+            StepState::Failed(e) if step.is_fatal() => format!("Sync failed: {}", e),
+            _ if progress.is_complete() => String::new(),
+            _ => format!("Loading… {}/{}", progress.finished(), progress.total()),
+        };
+        self.ui.set_sync_status(SharedString::from(status));
     }
 
-    pub async fn load_channel_list(&self) {
-        // TODO: run in parallel
-        self.model.list_joined_channels().await;
-        self.model.list_rooms().await;
-        let channels = self.model.get_joined_channels();
+    /// Shows the channels we know about, without fetching them again
+    /// Favorites go first and hidden channels aren't shown
+    fn update_channel_list(&self) {
+        let joined_channels = self.model.get_joined_channels();
+        let mut channels: Vec<&rocketchat::Room> = joined_channels
+            .iter()
+            .map(|c| c.as_ref())
+            .filter(|c| c.subscription().map(|s| s.open).unwrap_or(true))
+            .collect();
+        channels.sort_by_key(|c| !c.subscription().map(|s| s.favorite).unwrap_or(false));
+
         let mut ui_channels: Vec<Channel> = Vec::new();
         for c in channels {
+            let subscription = c.subscription().cloned().unwrap_or_default();
             ui_channels.push(Channel {
-                id: SharedString::from(c.id.clone()),
-                name: SharedString::from(c.name.clone()),
+                id: SharedString::from(c.id()),
+                name: SharedString::from(c.display_name()),
+                favorite: subscription.favorite,
+                muted: subscription.muted,
+                mentions: self.model.mention_counters(c.id()).total() as i32,
             });
         }
         let channel_model = Rc::new(slint::VecModel::from(ui_channels));
         self.ui.set_channelModel(channel_model.into());
-
-        let rooms = self.model.get_direct_rooms();
-        for r in rooms {
-            println!("direct room: {:?}", r.usernames);
-        }
-
-        let rooms = self.model.get_channel_rooms();
-        for r in rooms {
-            println!("channel room: {:?}", r.name);
-        }
     }
 }
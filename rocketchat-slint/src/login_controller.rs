@@ -1,26 +1,22 @@
 // SPDX-License-Identifier: MIT
 // SPDX-FileCopyrightText: Sergio Martins
 
-use crate::signal::Signal;
 use crate::slint_generatedAppWindow::AppWindow;
+use rocketchat::{Event, LoginState};
 use slint::{ComponentHandle, SharedString};
-use std::rc::Rc;
+use std::{rc::Rc, sync::Arc};
+use tokio::sync::broadcast::error::RecvError;
 
 pub struct Controller {
     ui: AppWindow,
-    model: Rc<rocketchat::RocketChat>,
-    pub login_changed: Signal,
+    model: Arc<rocketchat::RocketChat>,
 }
 
 impl Controller {
-    pub fn new(ui: AppWindow, model: Rc<rocketchat::RocketChat>) -> Rc<Self> {
+    pub fn new(ui: AppWindow, model: Arc<rocketchat::RocketChat>) -> Rc<Self> {
         ui.set_usernameText(SharedString::from(env!("RC_SLINT_USER")));
 
-        let controller = Rc::new(Self {
-            ui,
-            model,
-            login_changed: crate::signal::new(),
-        });
+        let controller = Rc::new(Self { ui, model });
 
         let controller_copy = controller.clone();
         controller
@@ -29,32 +25,83 @@ impl Controller {
                 controller_copy.login(username, password);
             });
 
+        let ui = controller.ui.clone_strong();
+        let mut events = controller.model.subscribe();
+        slint::spawn_local(async move {
+            loop {
+                match events.recv().await {
+                    Ok(Event::LoginChanged(state)) => {
+                        if state == LoginState::SessionExpired {
+                            log::warn!("session expired, please login again");
+                        }
+                        ui.set_logged_in(state == LoginState::LoggedIn);
+                    }
+                    Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        })
+        .unwrap();
+
+        let controller_copy = controller.clone();
+        controller.ui.on_request_logout(move || {
+            controller_copy.logout();
+        });
+
         controller
     }
 
     fn login(&self, username: SharedString, password: SharedString) {
-        println!("login requested");
+        log::debug!("login requested");
 
         let model = self.model.clone();
-        let ui = self.ui.clone_strong();
-        let sig = self.login_changed.clone();
 
-        slint::spawn_local(async move {
-            let result = model.login(&username, &password).await;
-            let is_logged_in = result.is_ok();
-            ui.set_logged_in(is_logged_in);
-            sig.emit();
-            if let Err(e) = result {
-                println!("slint: login failed: {}", e);
+        tokio::spawn(async move {
+            if let Err(e) = model.login(&username, &password).await {
+                log::error!("login failed: {}", e);
             }
-        })
-        .unwrap();
+        });
+    }
+
+    fn logout(&self) {
+        log::debug!("logout requested");
+
+        let model = self.model.clone();
+        let ui = self.ui.as_weak();
+
+        tokio::spawn(async move {
+            if let Err(e) = model.logout().await {
+                log::error!("logout failed: {}", e);
+            }
+            ui.upgrade_in_event_loop(|ui| ui.set_passwordText(SharedString::new()))
+                .unwrap_or_else(|e| log::warn!("{}", e));
+        });
     }
 
-    pub async fn login_via_saved_token(&self) {
-        let result = self.model.login_via_saved_token().await;
-        let is_logged_in = result.is_ok() && result.unwrap();
-        self.ui.set_logged_in(is_logged_in);
-        self.login_changed.emit();
+    /// Adapts the login screen to what the server supports
+    pub fn load_server_info(&self) {
+        let model = self.model.clone();
+        let ui = self.ui.as_weak();
+
+        tokio::spawn(async move {
+            let info = match model.server_info().await {
+                Ok(info) => info,
+                Err(e) => {
+                    log::error!("failed to get server info: {}", e);
+                    return;
+                }
+            };
+
+            ui.upgrade_in_event_loop(move |ui| {
+                ui.set_password_login_enabled(!info.login_methods.only_oauth());
+                if !info.is_supported() {
+                    ui.set_server_warning(SharedString::from(format!(
+                        "Server version {} is not supported",
+                        info.version
+                    )));
+                }
+            })
+            .unwrap_or_else(|e| log::warn!("{}", e));
+        });
     }
 }
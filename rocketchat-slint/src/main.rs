@@ -1,39 +1,42 @@
 // SPDX-License-Identifier: MIT
 // SPDX-FileCopyrightText: Sergio Martins
 
-use std::rc::Rc;
+use std::sync::Arc;
 
 mod channel_list_controller;
 mod login_controller;
-mod signal;
+mod sync;
 
 slint::include_modules!();
 
 #[tokio::main]
 async fn main() -> Result<(), slint::PlatformError> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
     let ui = AppWindow::new()?;
 
     pub const RC_SLINT_URL: &str = env!("RC_SLINT_URL");
 
-    let rc = Rc::new(rocketchat::RocketChat::new(
-        std::format!("https://{}", RC_SLINT_URL).as_str(),
-        rocketchat::RocketChat::saved_auth_token().as_str(),
-    ));
-
-    let login_controller = login_controller::Controller::new(ui.clone_strong(), Rc::clone(&rc));
+    let url = std::format!("https://{}", RC_SLINT_URL);
+    let paths = rocketchat::Paths::platform_default()
+        .unwrap_or_else(|e| {
+            log::warn!("{}, keeping files in the temp dir instead", e);
+            rocketchat::Paths::in_dir(&std::env::temp_dir().join("rc-slint"))
+        })
+        .for_server(&url);
+    let rc = Arc::new(rocketchat::RocketChat::new(&url, paths));
 
-    let channel_list_controller =
-        channel_list_controller::Controller::new(ui.clone_strong(), Rc::clone(&rc));
+    let login_controller = login_controller::Controller::new(ui.clone_strong(), Arc::clone(&rc));
 
-    login_controller.login_changed.connect(move || {
-        channel_list_controller::on_login_changed(channel_list_controller.clone());
-    });
+    let _channel_list_controller =
+        channel_list_controller::Controller::new(ui.clone_strong(), Arc::clone(&rc));
 
-    login_controller.login_via_saved_token().await;
+    login_controller.load_server_info();
+    sync::start(Arc::clone(&rc));
 
     let result = ui.run();
 
-    println!("Shutdown");
+    log::info!("Shutdown");
 
     result
 }
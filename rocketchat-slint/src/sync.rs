@@ -0,0 +1,61 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: Sergio Martins
+
+use rocketchat::{Event, LoginState, RocketChat};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::broadcast::error::RecvError;
+
+/// How often messages left in the outbox are sent again, while logged in
+const OUTBOX_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long to wait before reconnecting, once the realtime connection drops
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Keeps the model up to date from a tokio worker, so the UI thread never waits on the network
+/// Controllers learn about the results through the model's events
+pub fn start(model: Arc<RocketChat>) -> tokio::task::JoinHandle<()> {
+    let mut events = model.subscribe();
+    tokio::spawn(async move {
+        if let Err(e) = model.login_via_saved_token().await {
+            log::warn!("login via saved token failed: {}", e);
+        }
+
+        let mut outbox_retry = tokio::time::interval(OUTBOX_RETRY_INTERVAL);
+        // Brings in new messages, room changes and presence, while logged in
+        let mut follower: Option<tokio::task::JoinHandle<()>> = None;
+        loop {
+            let event = tokio::select! {
+                event = events.recv() => event,
+                _ = outbox_retry.tick() => {
+                    if model.is_logged_in() {
+                        model.flush_outbox().await;
+                    }
+                    continue;
+                }
+            };
+
+            match event {
+                Ok(Event::LoginChanged(state)) => {
+                    if let Some(follower) = follower.take() {
+                        follower.abort();
+                    }
+                    if state != LoginState::LoggedIn {
+                        continue;
+                    }
+
+                    if let Err(e) = model.initial_sync().await {
+                        log::error!("initial sync failed: {}", e);
+                    }
+                    model.flush_outbox().await;
+
+                    let model = Arc::clone(&model);
+                    follower = Some(tokio::spawn(async move {
+                        model.follow_realtime(RECONNECT_DELAY).await
+                    }));
+                }
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => break,
+            }
+        }
+    })
+}
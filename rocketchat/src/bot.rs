@@ -0,0 +1,457 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: Sergio Martins
+
+//! Bots: handlers for commands, patterns, mentions and events
+//!
+//! The bot follows its rooms with RocketChat::follow_realtime(), handlers only see the events
+//! that come out of it.
+
+use crate::{Event, Message, RocketChat, Room};
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{sync::broadcast::error::RecvError, time::Instant};
+
+type HandlerFuture = Pin<Box<dyn Future<Output = Result<(), String>> + Send>>;
+type Handler<T> = Arc<dyn Fn(T) -> HandlerFuture + Send + Sync>;
+
+fn handler<T, F, Fut>(f: F) -> Handler<T>
+where
+    F: Fn(T) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<(), String>> + Send + 'static,
+{
+    Arc::new(move |arg| Box::pin(f(arg)))
+}
+
+/// What makes a message handler run
+pub enum Trigger {
+    /// The message starts with the prefix and this name, for example "!deploy"
+    Command(String),
+    /// The message matches, anywhere in the text
+    Pattern(regex::Regex),
+    /// The message mentions the bot
+    Mention,
+}
+
+impl Trigger {
+    /// Returns the arguments to hand to the handler, if `message` triggers it
+    /// Commands and mentions get the remaining words, patterns get their capture groups
+    pub fn arguments(
+        &self,
+        message: &Message,
+        prefix: &str,
+        username: &str,
+    ) -> Option<Vec<String>> {
+        let words = |text: &str| text.split_whitespace().map(String::from).collect();
+        match self {
+            Trigger::Command(name) => {
+                let rest = message
+                    .text
+                    .strip_prefix(prefix)?
+                    .strip_prefix(name.as_str())?;
+                if rest.is_empty() || rest.starts_with(char::is_whitespace) {
+                    Some(words(rest))
+                } else {
+                    None
+                }
+            }
+            Trigger::Pattern(regex) => regex.captures(&message.text).map(|captures| {
+                captures
+                    .iter()
+                    .skip(1)
+                    .map(|c| c.map(|c| String::from(c.as_str())).unwrap_or_default())
+                    .collect()
+            }),
+            Trigger::Mention => {
+                if !message.mentions.iter().any(|m| m == username) {
+                    return None;
+                }
+                let mention = format!("@{}", username);
+                Some(
+                    message
+                        .text
+                        .split_whitespace()
+                        .filter(|word| *word != mention)
+                        .map(String::from)
+                        .collect(),
+                )
+            }
+        }
+    }
+}
+
+/// Limits how many messages the bot posts per room
+struct RoomLimiter {
+    max: usize,
+    per: Duration,
+    sent: Mutex<HashMap<String, VecDeque<Instant>>>,
+}
+
+impl RoomLimiter {
+    /// Waits until the bot may post in `room_id` again
+    async fn acquire(&self, room_id: &str) {
+        loop {
+            let wait = {
+                let mut sent = self.sent.lock().unwrap();
+                let times = sent.entry(String::from(room_id)).or_default();
+                let now = Instant::now();
+                while times
+                    .front()
+                    .is_some_and(|t| now.duration_since(*t) >= self.per)
+                {
+                    times.pop_front();
+                }
+                if times.len() < self.max {
+                    times.push_back(now);
+                    return;
+                }
+                self.per - now.duration_since(times[0])
+            };
+            log::debug!("bot: rate limited in {}, waiting {:?}", room_id, wait);
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// What a message handler gets to work with
+#[derive(Clone)]
+pub struct Context {
+    pub message: Message,
+    /// See Trigger::arguments(), empty for on_message() handlers
+    pub args: Vec<String>,
+    rc: Arc<RocketChat>,
+    limiter: Arc<RoomLimiter>,
+}
+
+impl Context {
+    pub fn rc(&self) -> &RocketChat {
+        &self.rc
+    }
+
+    /// Answers in the same room, or in the same thread if the message was in one
+    pub async fn reply(&self, text: &str) -> Result<Message, String> {
+        self.limiter.acquire(&self.message.room_id).await;
+        match &self.message.thread_id {
+            Some(thread_id) => {
+                self.rc
+                    .send_thread_reply(&self.message.room_id, thread_id, text)
+                    .await
+            }
+            None => self.rc.send_message(&self.message.room_id, text).await,
+        }
+    }
+
+    /// Answers in a thread started from the message
+    pub async fn reply_in_thread(&self, text: &str) -> Result<Message, String> {
+        self.limiter.acquire(&self.message.room_id).await;
+        let thread_id = self.message.thread_id.as_ref().unwrap_or(&self.message.id);
+        self.rc
+            .send_thread_reply(&self.message.room_id, thread_id, text)
+            .await
+    }
+
+    pub async fn react(&self, emoji: &str) -> Result<(), String> {
+        self.rc.react(&self.message.id, emoji, true).await
+    }
+}
+
+/// A reaction, as handed to on_reaction() handlers
+#[derive(Clone, Debug)]
+pub struct Reaction {
+    pub message_id: String,
+    pub emoji: String,
+    pub username: String,
+    pub added: bool,
+}
+
+/// Dispatches events to handlers, until shut down
+pub struct Bot {
+    rc: Arc<RocketChat>,
+    prefix: String,
+    reconnect_delay: Duration,
+    limiter: Arc<RoomLimiter>,
+    triggers: Vec<(Trigger, Handler<Context>)>,
+    message_handlers: Vec<Handler<Context>>,
+    joined_handlers: Vec<Handler<Arc<Room>>>,
+    reaction_handlers: Vec<Handler<Reaction>>,
+}
+
+impl Bot {
+    /// `rc` needs to be logged in, with list_rooms() called, before run()
+    /// Defaults to "!" commands, and posting at most 5 messages per room every 10 seconds
+    pub fn new(rc: Arc<RocketChat>) -> Bot {
+        Bot {
+            rc,
+            prefix: String::from("!"),
+            reconnect_delay: Duration::from_secs(30),
+            limiter: Arc::new(RoomLimiter {
+                max: 5,
+                per: Duration::from_secs(10),
+                sent: Mutex::default(),
+            }),
+            triggers: Vec::new(),
+            message_handlers: Vec::new(),
+            joined_handlers: Vec::new(),
+            reaction_handlers: Vec::new(),
+        }
+    }
+
+    pub fn set_prefix(&mut self, prefix: &str) {
+        self.prefix = String::from(prefix);
+    }
+
+    /// Allows at most `max` messages per room within `per`, further replies wait
+    pub fn set_rate_limit(&mut self, max: usize, per: Duration) {
+        self.limiter = Arc::new(RoomLimiter {
+            max,
+            per,
+            sent: Mutex::default(),
+        });
+    }
+
+    /// How long to wait before reconnecting, once the realtime connection drops
+    pub fn set_reconnect_delay(&mut self, delay: Duration) {
+        self.reconnect_delay = delay;
+    }
+
+    /// Runs `f` for messages like "!name arg1 arg2"
+    pub fn command<F, Fut>(&mut self, name: &str, f: F)
+    where
+        F: Fn(Context) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        self.triggers
+            .push((Trigger::Command(String::from(name)), handler(f)));
+    }
+
+    /// Runs `f` for messages matching `regex`
+    pub fn pattern<F, Fut>(&mut self, regex: regex::Regex, f: F)
+    where
+        F: Fn(Context) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        self.triggers.push((Trigger::Pattern(regex), handler(f)));
+    }
+
+    /// Runs `f` for messages mentioning the bot
+    pub fn on_mention<F, Fut>(&mut self, f: F)
+    where
+        F: Fn(Context) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        self.triggers.push((Trigger::Mention, handler(f)));
+    }
+
+    /// Runs `f` for every message, except the bot's own
+    pub fn on_message<F, Fut>(&mut self, f: F)
+    where
+        F: Fn(Context) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        self.message_handlers.push(handler(f));
+    }
+
+    /// Runs `f` when the bot joins a room, or is added to one
+    pub fn on_joined<F, Fut>(&mut self, f: F)
+    where
+        F: Fn(Arc<Room>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        self.joined_handlers.push(handler(f));
+    }
+
+    /// Runs `f` when someone reacts to a recent message, or takes their reaction back
+    pub fn on_reaction<F, Fut>(&mut self, f: F)
+    where
+        F: Fn(Reaction) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        self.reaction_handlers.push(handler(f));
+    }
+
+    /// Handles events until `shutdown` completes, then waits for running handlers to finish
+    pub async fn run<S>(self, shutdown: S) -> Result<(), String>
+    where
+        S: Future<Output = ()>,
+    {
+        if !self.rc.is_logged_in() {
+            return Err(String::from("bot: not logged in"));
+        }
+
+        let mut events = self.rc.subscribe();
+        let watcher = {
+            let rc = self.rc.clone();
+            let reconnect_delay = self.reconnect_delay;
+            tokio::spawn(async move { rc.follow_realtime(reconnect_delay).await })
+        };
+        let mut running = tokio::task::JoinSet::new();
+        tokio::pin!(shutdown);
+
+        loop {
+            let event = tokio::select! {
+                _ = &mut shutdown => break,
+                event = events.recv() => event,
+            };
+
+            match event {
+                Ok(event) => {
+                    for task in self.handlers_for(event) {
+                        running.spawn(task);
+                    }
+                }
+                Err(RecvError::Lagged(missed)) => log::warn!("bot: missed {} events", missed),
+                Err(RecvError::Closed) => break,
+            }
+
+            // Reap what finished, so errors get logged as they happen
+            while let Some(result) = running.try_join_next() {
+                log_result(result);
+            }
+        }
+
+        log::info!("bot: shutting down, waiting for {} handlers", running.len());
+        watcher.abort();
+        while let Some(result) = running.join_next().await {
+            log_result(result);
+        }
+        Ok(())
+    }
+
+    /// Returns the handler invocations an event calls for
+    fn handlers_for(&self, event: Event) -> Vec<HandlerFuture> {
+        match event {
+            Event::MessageReceived(message) => {
+                let username = self.rc.get_username();
+                if message.author_username == username {
+                    return Vec::new();
+                }
+
+                let context = |args: Vec<String>| Context {
+                    message: message.clone(),
+                    args,
+                    rc: self.rc.clone(),
+                    limiter: self.limiter.clone(),
+                };
+
+                self.triggers
+                    .iter()
+                    .filter_map(|(trigger, handler)| {
+                        let args = trigger.arguments(&message, &self.prefix, &username)?;
+                        Some(handler(context(args)))
+                    })
+                    .chain(
+                        self.message_handlers
+                            .iter()
+                            .map(|handler| handler(context(Vec::new()))),
+                    )
+                    .collect()
+            }
+            Event::RoomAdded(room) => self
+                .joined_handlers
+                .iter()
+                .map(|handler| handler(room.clone()))
+                .collect(),
+            Event::ReactionChanged {
+                message_id,
+                emoji,
+                username,
+                added,
+            } => {
+                let reaction = Reaction {
+                    message_id,
+                    emoji,
+                    username,
+                    added,
+                };
+                self.reaction_handlers
+                    .iter()
+                    .map(|handler| handler(reaction.clone()))
+                    .collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+fn log_result(result: Result<Result<(), String>, tokio::task::JoinError>) {
+    match result {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => log::warn!("bot: handler failed: {}", e),
+        Err(e) => log::error!("bot: handler panicked: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(text: &str, mentions: &[&str]) -> Message {
+        Message::from_json(&serde_json::json!({
+            "_id": "msg1",
+            "rid": "GENERAL",
+            "msg": text,
+            "u": { "_id": "u1", "username": "alice" },
+            "mentions": mentions
+                .iter()
+                .map(|m| serde_json::json!({ "username": m }))
+                .collect::<Vec<_>>()
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_trigger_arguments() {
+        let deploy = Trigger::Command(String::from("deploy"));
+        assert_eq!(
+            deploy.arguments(&message("!deploy web prod", &[]), "!", "bot"),
+            Some(vec![String::from("web"), String::from("prod")])
+        );
+        assert_eq!(
+            deploy.arguments(&message("!deploy", &[]), "!", "bot"),
+            Some(Vec::new())
+        );
+        assert_eq!(
+            deploy.arguments(&message("!deployment", &[]), "!", "bot"),
+            None
+        );
+        assert_eq!(deploy.arguments(&message("deploy", &[]), "!", "bot"), None);
+
+        let pattern = Trigger::Pattern(regex::Regex::new(r"ticket #(\d+)").unwrap());
+        assert_eq!(
+            pattern.arguments(&message("see ticket #42", &[]), "!", "bot"),
+            Some(vec![String::from("42")])
+        );
+
+        let mention = Trigger::Mention;
+        assert_eq!(
+            mention.arguments(&message("@bot status please", &["bot"]), "!", "bot"),
+            Some(vec![String::from("status"), String::from("please")])
+        );
+        assert_eq!(
+            mention.arguments(&message("@carol hi", &["carol"]), "!", "bot"),
+            None
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_room_limiter() {
+        let limiter = RoomLimiter {
+            max: 2,
+            per: Duration::from_secs(10),
+            sent: Mutex::default(),
+        };
+
+        let start = tokio::time::Instant::now();
+        limiter.acquire("GENERAL").await;
+        limiter.acquire("GENERAL").await;
+        limiter.acquire("random").await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        limiter.acquire("GENERAL").await;
+        assert!(start.elapsed() >= Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,184 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: Sergio Martins
+
+use crate::RoomType;
+
+/// What to look for in the directory
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DirectoryKind {
+    Channels,
+    Users,
+    Teams,
+}
+
+impl DirectoryKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DirectoryKind::Channels => "channels",
+            DirectoryKind::Users => "users",
+            DirectoryKind::Teams => "teams",
+        }
+    }
+}
+
+/// How to sort results, for example by "name" or "usersCount"
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sort {
+    pub field: String,
+    pub ascending: bool,
+}
+
+impl Sort {
+    pub fn ascending(field: &str) -> Sort {
+        Sort {
+            field: field.to_string(),
+            ascending: true,
+        }
+    }
+
+    pub fn descending(field: &str) -> Sort {
+        Sort {
+            field: field.to_string(),
+            ascending: false,
+        }
+    }
+
+    /// The format expected by the `sort` query parameter, for example `{"name":1}`
+    pub fn to_json(&self) -> String {
+        serde_json::json!({ &self.field: if self.ascending { 1 } else { -1 } }).to_string()
+    }
+}
+
+/// A channel or team, as listed in the directory
+#[derive(Clone, Debug, PartialEq)]
+pub struct DirectoryRoom {
+    pub id: String,
+    pub name: String,
+    pub topic: Option<String>,
+    pub users_count: u64,
+    pub room_type: RoomType,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DirectoryUser {
+    pub id: String,
+    pub username: String,
+    pub name: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DirectoryEntry {
+    Room(DirectoryRoom),
+    User(DirectoryUser),
+}
+
+/// One page of directory results
+#[derive(Clone, Debug, PartialEq)]
+pub struct DirectoryPage {
+    pub entries: Vec<DirectoryEntry>,
+    /// Number of results across all pages
+    pub total: u64,
+}
+
+impl DirectoryPage {
+    pub fn from_json(
+        kind: DirectoryKind,
+        body: &serde_json::Value,
+    ) -> Result<DirectoryPage, String> {
+        let entries = body["result"]
+            .as_array()
+            .ok_or("directory: result is missing")?
+            .iter()
+            .map(|r| -> Result<DirectoryEntry, String> {
+                let id = String::from(r["_id"].as_str().ok_or("directory: _id is missing")?);
+                Ok(match kind {
+                    DirectoryKind::Users => DirectoryEntry::User(DirectoryUser {
+                        id,
+                        username: String::from(r["username"].as_str().unwrap_or_default()),
+                        name: String::from(r["name"].as_str().unwrap_or_default()),
+                    }),
+                    DirectoryKind::Channels | DirectoryKind::Teams => {
+                        DirectoryEntry::Room(DirectoryRoom {
+                            id,
+                            name: String::from(
+                                r["fname"]
+                                    .as_str()
+                                    .or(r["name"].as_str())
+                                    .unwrap_or_default(),
+                            ),
+                            topic: r["topic"].as_str().map(String::from),
+                            users_count: r["usersCount"].as_u64().unwrap_or(0),
+                            room_type: RoomType::from_json(r),
+                        })
+                    }
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(DirectoryPage {
+            total: body["total"].as_u64().unwrap_or(entries.len() as u64),
+            entries,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_directory_page_from_json() {
+        let body = serde_json::json!({
+            "result": [
+                { "_id": "r1", "name": "general", "t": "c", "usersCount": 42, "topic": "hi" },
+                { "_id": "r2", "name": "team", "t": "p", "teamMain": true }
+            ],
+            "count": 2,
+            "offset": 0,
+            "total": 10,
+            "success": true
+        });
+
+        let page = DirectoryPage::from_json(DirectoryKind::Channels, &body).unwrap();
+        assert_eq!(page.total, 10);
+        assert_eq!(
+            page.entries[0],
+            DirectoryEntry::Room(DirectoryRoom {
+                id: String::from("r1"),
+                name: String::from("general"),
+                topic: Some(String::from("hi")),
+                users_count: 42,
+                room_type: RoomType::Channel,
+            })
+        );
+        match &page.entries[1] {
+            DirectoryEntry::Room(room) => {
+                assert_eq!(room.room_type, RoomType::TeamMain { private: true })
+            }
+            _ => panic!("expected a room"),
+        }
+
+        let body = serde_json::json!({
+            "result": [ { "_id": "u1", "username": "sergio", "name": "Sergio" } ],
+            "total": 1
+        });
+        let page = DirectoryPage::from_json(DirectoryKind::Users, &body).unwrap();
+        assert_eq!(
+            page.entries,
+            vec![DirectoryEntry::User(DirectoryUser {
+                id: String::from("u1"),
+                username: String::from("sergio"),
+                name: String::from("Sergio"),
+            })]
+        );
+    }
+
+    #[test]
+    fn test_sort_to_json() {
+        assert_eq!(Sort::ascending("name").to_json(), r#"{"name":1}"#);
+        assert_eq!(
+            Sort::descending("usersCount").to_json(),
+            r#"{"usersCount":-1}"#
+        );
+    }
+}
@@ -0,0 +1,259 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: Sergio Martins
+
+/// Shortcodes, as named by Rocket.Chat (EmojiOne), and their Unicode, sorted by shortcode
+/// Not exhaustive, but covers what people actually type. Aliases have their own entry.
+const SHORTCODES: &[(&str, &str)] = &[
+    ("+1", "👍"),
+    ("-1", "👎"),
+    ("100", "💯"),
+    ("alarm_clock", "⏰"),
+    ("angry", "😠"),
+    ("arrow_down", "⬇️"),
+    ("arrow_left", "⬅️"),
+    ("arrow_right", "➡️"),
+    ("arrow_up", "⬆️"),
+    ("astonished", "😲"),
+    ("baby", "👶"),
+    ("balloon", "🎈"),
+    ("beer", "🍺"),
+    ("beers", "🍻"),
+    ("bell", "🔔"),
+    ("birthday", "🎂"),
+    ("blush", "😊"),
+    ("bomb", "💣"),
+    ("books", "📚"),
+    ("boom", "💥"),
+    ("broken_heart", "💔"),
+    ("bug", "🐛"),
+    ("bulb", "💡"),
+    ("cake", "🍰"),
+    ("calendar", "📆"),
+    ("camera", "📷"),
+    ("cat", "🐱"),
+    ("champagne", "🍾"),
+    ("chart_with_upwards_trend", "📈"),
+    ("check", "✔️"),
+    ("clap", "👏"),
+    ("clock", "🕐"),
+    ("cloud", "☁️"),
+    ("coffee", "☕"),
+    ("cold_sweat", "😰"),
+    ("computer", "💻"),
+    ("confetti_ball", "🎊"),
+    ("confused", "😕"),
+    ("construction", "🚧"),
+    ("cool", "🆒"),
+    ("cop", "👮"),
+    ("cry", "😢"),
+    ("crying_cat_face", "😿"),
+    ("dancer", "💃"),
+    ("disappointed", "😞"),
+    ("dizzy_face", "😵"),
+    ("dog", "🐶"),
+    ("dollar", "💵"),
+    ("eyes", "👀"),
+    ("facepalm", "🤦"),
+    ("fearful", "😨"),
+    ("fire", "🔥"),
+    ("fist", "✊"),
+    ("flushed", "😳"),
+    ("frowning", "😦"),
+    ("gem", "💎"),
+    ("ghost", "👻"),
+    ("gift", "🎁"),
+    ("grimacing", "😬"),
+    ("grin", "😁"),
+    ("grinning", "😀"),
+    ("hammer", "🔨"),
+    ("hand_splayed", "🖐️"),
+    ("handshake", "🤝"),
+    ("heart", "❤️"),
+    ("heart_eyes", "😍"),
+    ("heavy_check_mark", "✔️"),
+    ("heavy_multiplication_x", "✖️"),
+    ("hot_pepper", "🌶️"),
+    ("hourglass", "⌛"),
+    ("hugging", "🤗"),
+    ("hushed", "😯"),
+    ("information_source", "ℹ️"),
+    ("innocent", "😇"),
+    ("joy", "😂"),
+    ("key", "🔑"),
+    ("kiss", "💋"),
+    ("kissing_heart", "😘"),
+    ("laughing", "😆"),
+    ("link", "🔗"),
+    ("lock", "🔒"),
+    ("mag", "🔍"),
+    ("mask", "😷"),
+    ("memo", "📝"),
+    ("metal", "🤘"),
+    ("money_mouth", "🤑"),
+    ("moon", "🌙"),
+    ("muscle", "💪"),
+    ("nerd", "🤓"),
+    ("neutral_face", "😐"),
+    ("no_entry", "⛔"),
+    ("no_mouth", "😶"),
+    ("ok", "🆗"),
+    ("ok_hand", "👌"),
+    ("open_mouth", "😮"),
+    ("package", "📦"),
+    ("paperclip", "📎"),
+    ("partying_face", "🥳"),
+    ("pencil", "📝"),
+    ("pensive", "😔"),
+    ("persevere", "😣"),
+    ("phone", "☎️"),
+    ("pizza", "🍕"),
+    ("point_down", "👇"),
+    ("point_left", "👈"),
+    ("point_right", "👉"),
+    ("point_up", "☝️"),
+    ("poop", "💩"),
+    ("pray", "🙏"),
+    ("question", "❓"),
+    ("rage", "😡"),
+    ("raised_hand", "✋"),
+    ("raised_hands", "🙌"),
+    ("recycle", "♻️"),
+    ("relaxed", "☺️"),
+    ("relieved", "😌"),
+    ("robot", "🤖"),
+    ("rocket", "🚀"),
+    ("rofl", "🤣"),
+    ("rolling_eyes", "🙄"),
+    ("rotating_light", "🚨"),
+    ("scream", "😱"),
+    ("see_no_evil", "🙈"),
+    ("shrug", "🤷"),
+    ("skull", "💀"),
+    ("sleeping", "😴"),
+    ("sleepy", "😪"),
+    ("slight_frown", "🙁"),
+    ("slight_smile", "🙂"),
+    ("smile", "😄"),
+    ("smiley", "😃"),
+    ("smirk", "😏"),
+    ("sob", "😭"),
+    ("sparkles", "✨"),
+    ("speech_balloon", "💬"),
+    ("star", "⭐"),
+    ("star_struck", "🤩"),
+    ("stuck_out_tongue", "😛"),
+    ("stuck_out_tongue_winking_eye", "😜"),
+    ("sunglasses", "😎"),
+    ("sunny", "☀️"),
+    ("sweat", "😓"),
+    ("sweat_smile", "😅"),
+    ("tada", "🎉"),
+    ("thinking", "🤔"),
+    ("thumbsdown", "👎"),
+    ("thumbsup", "👍"),
+    ("tired_face", "😫"),
+    ("tongue", "👅"),
+    ("trophy", "🏆"),
+    ("turtle", "🐢"),
+    ("unamused", "😒"),
+    ("unlock", "🔓"),
+    ("upside_down", "🙃"),
+    ("v", "✌️"),
+    ("warning", "⚠️"),
+    ("wave", "👋"),
+    ("weary", "😩"),
+    ("white_check_mark", "✅"),
+    ("wink", "😉"),
+    ("worried", "😟"),
+    ("wrench", "🔧"),
+    ("x", "❌"),
+    ("yum", "😋"),
+    ("zany_face", "🤪"),
+    ("zap", "⚡"),
+    ("zipper_mouth", "🤐"),
+    ("zzz", "💤"),
+];
+
+/// Returns the Unicode for a standard shortcode, without the colons
+pub fn unicode_for(shortcode: &str) -> Option<&'static str> {
+    SHORTCODES
+        .binary_search_by(|(code, _)| (*code).cmp(shortcode))
+        .ok()
+        .map(|i| SHORTCODES[i].1)
+}
+
+/// An emoji uploaded to the server by its admins
+#[derive(Clone, Debug, PartialEq)]
+pub struct CustomEmoji {
+    pub name: String,
+    pub aliases: Vec<String>,
+    /// Image format, for example "png"
+    pub extension: String,
+}
+
+impl CustomEmoji {
+    pub fn from_json(e: &serde_json::Value) -> Result<CustomEmoji, String> {
+        Ok(CustomEmoji {
+            name: String::from(e["name"].as_str().ok_or("emoji name is missing")?),
+            aliases: e["aliases"]
+                .as_array()
+                .map(|aliases| {
+                    aliases
+                        .iter()
+                        .filter_map(|a| a.as_str().map(String::from))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            extension: String::from(e["extension"].as_str().unwrap_or("png")),
+        })
+    }
+
+    pub fn matches(&self, shortcode: &str) -> bool {
+        self.name == shortcode || self.aliases.iter().any(|a| a == shortcode)
+    }
+
+    /// Where the image is served, relative to the server URL
+    pub fn image_path(&self) -> String {
+        format!("emoji-custom/{}.{}", self.name, self.extension)
+    }
+}
+
+/// What a shortcode resolves to
+#[derive(Clone, Debug, PartialEq)]
+pub enum Emoji {
+    Unicode(&'static str),
+    Custom(CustomEmoji),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shortcodes_are_sorted() {
+        assert!(SHORTCODES.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+
+    #[test]
+    fn test_unicode_for() {
+        assert_eq!(unicode_for("thumbsup"), Some("👍"));
+        assert_eq!(unicode_for("+1"), Some("👍"));
+        assert_eq!(unicode_for("tada"), Some("🎉"));
+        assert_eq!(unicode_for("not_an_emoji"), None);
+    }
+
+    #[test]
+    fn test_custom_emoji_from_json() {
+        let json = serde_json::json!({
+            "_id": "e1",
+            "name": "party_parrot",
+            "aliases": ["parrot"],
+            "extension": "gif"
+        });
+
+        let emoji = CustomEmoji::from_json(&json).unwrap();
+        assert!(emoji.matches("parrot"));
+        assert!(!emoji.matches("party"));
+        assert_eq!(emoji.image_path(), "emoji-custom/party_parrot.gif");
+    }
+}
@@ -0,0 +1,53 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: Sergio Martins
+
+use crate::{Message, OutboxMessage, Room, StepState, SyncStep, UserStatus};
+use std::sync::Arc;
+
+/// How many events a slow subscriber can fall behind before it starts missing them
+pub(crate) const EVENT_CAPACITY: usize = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoginState {
+    LoggedIn,
+    LoggedOut,
+    /// The session couldn't be recovered, the user needs to login again
+    SessionExpired,
+}
+
+/// Something that changed in our local state, see RocketChat::subscribe()
+#[derive(Clone, Debug)]
+pub enum Event {
+    LoginChanged(LoginState),
+    /// We joined, created or opened a room
+    RoomAdded(Arc<Room>),
+    /// The room or our subscription to it changed
+    RoomUpdated(Arc<Room>),
+    /// We left the room, by ID
+    RoomRemoved(String),
+    /// The whole room list was fetched again
+    RoomsReset,
+    /// A new message in one of our rooms, see RocketChat::note_message()
+    MessageReceived(Message),
+    /// A message was pinned, starred or the like, by ID
+    MessageUpdated(String),
+    /// Someone reacted to a message, or took their reaction back
+    ReactionChanged {
+        message_id: String,
+        /// With the colons, for example ":tada:"
+        emoji: String,
+        username: String,
+        added: bool,
+    },
+    /// Someone's status changed, as reported by RocketChat::follow_realtime(), or ours
+    PresenceChanged {
+        user_id: String,
+        status: UserStatus,
+    },
+    /// initial_sync() started, finished or failed a step
+    SyncProgress(SyncStep, StepState),
+    /// A message we composed was queued, sent or failed
+    OutboxChanged(OutboxMessage),
+    /// A queued message was discarded, by ID
+    OutboxDiscarded(String),
+}
@@ -0,0 +1,260 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: Sergio Martins
+
+//! Renders a room's history for archiving, see RocketChat::export_room()
+
+use crate::{markdown, Message, Room};
+use chrono::TimeZone;
+use std::collections::{HashMap, HashSet};
+
+/// What export_room() writes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    /// The messages as sent by the server, nothing is lost
+    Json,
+    Markdown,
+    /// A single page, with threads nested under the message that started them
+    Html,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::Markdown => "md",
+            ExportFormat::Html => "html",
+        }
+    }
+}
+
+/// Which messages to export, as timestamps in seconds. None means unbounded.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ExportRange {
+    pub oldest: Option<i64>,
+    pub latest: Option<i64>,
+}
+
+/// Where the attachments ended up, by server path
+/// Values are relative to the exported file, or server URLs if the download failed.
+pub type Files = HashMap<String, String>;
+
+/// Replaces characters that aren't safe in file names
+pub fn file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '.' || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn time(timestamp: i64) -> String {
+    chrono::Utc
+        .timestamp_opt(timestamp, 0)
+        .single()
+        .map(|t| t.format("%Y-%m-%d %H:%M UTC").to_string())
+        .unwrap_or_default()
+}
+
+/// Groups `messages`, oldest first, into threads
+/// Replies whose parent is outside the exported range are shown as regular messages.
+fn threads(messages: &[Message]) -> Vec<(&Message, Vec<&Message>)> {
+    let ids: HashSet<&str> = messages.iter().map(|m| m.id.as_str()).collect();
+    let mut replies: HashMap<&str, Vec<&Message>> = HashMap::new();
+    let mut top_level = Vec::new();
+    for message in messages {
+        match &message.thread_id {
+            Some(parent) if ids.contains(parent.as_str()) => {
+                replies.entry(parent.as_str()).or_default().push(message)
+            }
+            _ => top_level.push(message),
+        }
+    }
+
+    top_level
+        .into_iter()
+        .map(|message| {
+            let replies = replies.remove(message.id.as_str()).unwrap_or_default();
+            (message, replies)
+        })
+        .collect()
+}
+
+pub fn to_json(room: &Room, messages: &[serde_json::Value], files: &Files) -> serde_json::Value {
+    serde_json::json!({
+        "room": {
+            "id": room.id(),
+            "name": room.display_name(),
+            "private": room.room_type().is_private(),
+            "direct": room.room_type().is_direct(),
+        },
+        "messages": messages,
+        "files": files,
+    })
+}
+
+fn markdown_message(message: &Message, files: &Files) -> String {
+    let mut text = format!(
+        "**{}** · {}\n\n{}\n",
+        message.author_username,
+        time(message.timestamp),
+        message.text
+    );
+    for attachment in &message.attachments {
+        let link = files.get(&attachment.path).unwrap_or(&attachment.path);
+        text += &format!("\n📎 [{}](<{}>)\n", attachment.title, link);
+        if let Some(description) = &attachment.description {
+            text += &format!("{}\n", description);
+        }
+    }
+    text
+}
+
+pub fn to_markdown(room: &Room, messages: &[Message], files: &Files) -> String {
+    let mut text = format!("# {}\n", room.display_name());
+    for (message, replies) in threads(messages) {
+        text += &format!("\n---\n\n{}", markdown_message(message, files));
+        for reply in replies {
+            // Quoting nests the reply under its parent
+            text += ">\n";
+            for line in markdown_message(reply, files).lines() {
+                text += &format!("> {}\n", line).replace("> \n", ">\n");
+            }
+        }
+    }
+    text
+}
+
+const STYLE: &str = "body { font-family: sans-serif; max-width: 50em; margin: auto; }
+.message { margin: 1em 0; }
+.author { font-weight: bold; }
+.time { color: #777; margin-left: 0.5em; font-size: 0.9em; }
+.thread { margin-left: 2em; padding-left: 1em; border-left: 3px solid #ccc; }
+.mention { color: #1d74f5; }
+pre { background: #f4f4f4; padding: 0.5em; overflow-x: auto; }
+img { max-width: 100%; }";
+
+fn is_image(path: &str) -> bool {
+    let path = path.to_lowercase();
+    [".png", ".jpg", ".jpeg", ".gif", ".webp"]
+        .iter()
+        .any(|extension| path.ends_with(extension))
+}
+
+fn html_message(message: &Message, files: &Files) -> String {
+    let mut html = format!(
+        "<div class=\"message\" id=\"{}\"><span class=\"author\">{}</span><span class=\"time\">{}</span>{}",
+        markdown::escape_html(&message.id),
+        markdown::escape_html(&message.author_username),
+        time(message.timestamp),
+        markdown::to_html(&message.ast())
+    );
+    for attachment in &message.attachments {
+        let link = markdown::escape_html(files.get(&attachment.path).unwrap_or(&attachment.path));
+        let title = markdown::escape_html(&attachment.title);
+        if is_image(&attachment.path) {
+            html += &format!("<p><img src=\"{}\" alt=\"{}\"></p>", link, title);
+        } else {
+            html += &format!("<p>📎 <a href=\"{}\">{}</a></p>", link, title);
+        }
+        if let Some(description) = &attachment.description {
+            html += &format!("<p>{}</p>", markdown::escape_html(description));
+        }
+    }
+    html
+}
+
+pub fn to_html(room: &Room, messages: &[Message], files: &Files) -> String {
+    let title = markdown::escape_html(&room.display_name());
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\n{}\n</style>\n</head>\n<body>\n<h1>{}</h1>\n",
+        title, STYLE, title
+    );
+    for (message, replies) in threads(messages) {
+        html += &html_message(message, files);
+        if !replies.is_empty() {
+            html += "<div class=\"thread\">";
+            for reply in replies {
+                html += &html_message(reply, files);
+                html += "</div>";
+            }
+            html += "</div>";
+        }
+        html += "</div>\n";
+    }
+    html + "</body>\n</html>\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn room() -> Room {
+        Room::Channel(crate::Channel::for_test("GENERAL", "incident-42"))
+    }
+
+    fn messages() -> Vec<serde_json::Value> {
+        vec![
+            serde_json::json!({
+                "_id": "m1", "rid": "GENERAL", "msg": "the *site* is down",
+                "ts": "2022-05-17T14:55:23.276Z", "u": { "_id": "u1", "username": "alice" },
+                "attachments": [{ "title": "graph.png", "image_url": "/file-upload/f1/graph.png" }]
+            }),
+            serde_json::json!({
+                "_id": "m2", "rid": "GENERAL", "msg": "on it <b>",
+                "ts": "2022-05-17T14:56:00.000Z", "u": { "_id": "u2", "username": "bob" },
+                "tmid": "m1"
+            }),
+            serde_json::json!({
+                "_id": "m3", "rid": "GENERAL", "msg": "late reply",
+                "ts": "2022-05-17T14:57:00.000Z", "u": { "_id": "u2", "username": "bob" },
+                "tmid": "older"
+            }),
+        ]
+    }
+
+    #[test]
+    fn test_threads() {
+        let messages = Message::list_from_json(&serde_json::json!(messages())).unwrap();
+        let threads = threads(&messages);
+        assert_eq!(threads.len(), 2);
+        assert_eq!(threads[0].0.id, "m1");
+        assert_eq!(threads[0].1.len(), 1);
+        assert_eq!(threads[1].0.id, "m3");
+    }
+
+    #[test]
+    fn test_export_formats() {
+        let raw = messages();
+        let messages = Message::list_from_json(&serde_json::json!(raw)).unwrap();
+        let files = Files::from([(
+            String::from("file-upload/f1/graph.png"),
+            String::from("incident-42_files/m1_graph.png"),
+        )]);
+
+        let json = to_json(&room(), &raw, &files);
+        assert_eq!(json["messages"][1], raw[1]);
+        assert_eq!(json["room"]["name"], "incident-42");
+
+        let markdown = to_markdown(&room(), &messages, &files);
+        assert!(markdown.starts_with("# incident-42\n"));
+        assert!(markdown.contains("[graph.png](<incident-42_files/m1_graph.png>)"));
+        assert!(markdown.contains("> **bob** · 2022-05-17 14:56 UTC\n>\n> on it <b>\n"));
+
+        let html = to_html(&room(), &messages, &files);
+        assert!(html.contains("<p>the <strong>site</strong> is down</p>"));
+        assert!(html.contains("<img src=\"incident-42_files/m1_graph.png\" alt=\"graph.png\">"));
+        assert!(html.contains("<div class=\"thread\"><div class=\"message\" id=\"m2\">"));
+        assert!(html.contains("on it &lt;b&gt;"));
+        assert_eq!(html.matches("<div").count(), html.matches("</div>").count());
+    }
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!(file_name("alice, bob"), "alice__bob");
+        assert_eq!(file_name("../etc"), ".._etc");
+    }
+}
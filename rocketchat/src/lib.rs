@@ -1,32 +1,75 @@
 // SPDX-License-Identifier: MIT
 // SPDX-FileCopyrightText: Sergio Martins
 
-use std::{collections::HashMap, sync::Mutex};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use chrono::TimeZone;
+use futures::StreamExt;
+
+pub mod bot;
+mod directory;
+mod emoji;
+mod event;
+mod export;
+pub mod markdown;
+mod members;
+mod mention;
+mod message;
+mod moderation;
+mod outbox;
+mod paths;
+mod rate_limit;
+mod realtime;
+mod redact;
+mod room;
+mod server_info;
+mod subscription;
+mod sync;
+
+pub use directory::{
+    DirectoryEntry, DirectoryKind, DirectoryPage, DirectoryRoom, DirectoryUser, Sort,
+};
+pub use emoji::{CustomEmoji, Emoji};
+pub use event::{Event, LoginState};
+pub use export::{ExportFormat, ExportRange};
+pub use members::{Member, MembersPage, RoomRoles, RoomUser, UserStatus};
+pub use mention::{MentionCounters, MentionKind};
+pub use message::{Attachment, Message, MessagesPage};
+pub use moderation::ModerationAction;
+use outbox::Outbox;
+pub use outbox::{OutboxMessage, OutboxState};
+pub use paths::Paths;
+pub use rate_limit::RateLimit;
+use rate_limit::RateLimiter;
+pub use realtime::{Realtime, RoomChange, Update};
+pub use room::{EndpointFamily, RoomType};
+pub use server_info::{LoginMethods, ServerInfo};
+pub use subscription::{NotificationLevel, NotificationPreferences, Subscription};
+pub use sync::{StepState, SyncProgress, SyncStep};
+
+/// Log target for every request and response, at trace level, with secrets redacted
+/// For example RUST_LOG=rocketchat::http=trace with env_logger
+pub const HTTP_LOG_TARGET: &str = "rocketchat::http";
 
 /// Represents the server
 pub struct RocketChat {
     url: String,
+    paths: Paths,
     exclusive_data: Mutex<ExclusiveData>,
-}
-
-enum RoomType {
-    /// A direct conversation with someone
-    Direct,
-    Unknown,
-    Channel,
-}
-
-impl RoomType {
-    pub fn from_str(s: &str) -> RoomType {
-        match s {
-            "d" => RoomType::Direct,
-            "c" => RoomType::Channel,
-            "p" => RoomType::Channel, // Locked channel
-            _ => RoomType::Unknown,
-        }
-    }
+    events: tokio::sync::broadcast::Sender<Event>,
+    rate_limiter: RateLimiter,
+    server_info: Mutex<Option<ServerInfo>>,
+    custom_emojis: Mutex<Option<Vec<CustomEmoji>>>,
+    outbox: Mutex<Outbox>,
+    /// Held while sending from the outbox, so nothing is sent twice or out of order
+    outbox_sending: tokio::sync::Mutex<()>,
+    /// Bumped whenever the session is resumed. Held while resuming.
+    session_generation: tokio::sync::Mutex<u64>,
 }
 
 /// Data that might be accessed by another thread
@@ -34,44 +77,306 @@ impl RoomType {
 struct ExclusiveData {
     auth_token: String,
     user_id: String,
-    joined_channels: Vec<Channel>,
-    pub direct_rooms: Vec<DirectRoom>,
-    pub channel_rooms: Vec<Channel>,
+    username: String,
+    /// Words that count as mentioning us
+    highlight_words: Vec<String>,
+    /// Mentions seen via note_message(), by room ID
+    mention_counters: HashMap<String, MentionCounters>,
+    // Rooms are replaced wholesale on change, so readers get cheap snapshots. The rooms
+    // themselves are shared between the lists, so a change only copies pointers.
+    joined_channels: Arc<[Arc<Room>]>,
+    direct_rooms: Arc<[Arc<Room>]>,
+    channel_rooms: Arc<[Arc<Room>]>,
+    /// Every room above, by ID
+    rooms_by_id: Arc<HashMap<String, Arc<Room>>>,
+    /// By room ID. Kept separately as rooms and subscriptions are fetched independently.
+    subscriptions: HashMap<String, Subscription>,
 }
 
-#[derive(Clone)]
+/// Any room that isn't a direct conversation: channels, groups, discussions, teams and livechat
+#[derive(Clone, Debug)]
 pub struct Channel {
     pub id: String,
     pub name: String,
+    pub room_type: RoomType,
+    /// The room a discussion belongs to
+    pub parent_id: Option<String>,
     pub num_msgs: u64,
     pub last_message_timestamp: Option<i64>,
+    /// None until list_subscriptions() is called
+    pub subscription: Option<Subscription>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct DirectRoom {
     pub id: String,
+    /// Either RoomType::Direct or RoomType::MultiUserDirect
+    pub room_type: RoomType,
     pub num_msgs: u64,
     pub usernames: Vec<String>,
     pub last_message_timestamp: Option<i64>,
+    /// None until list_subscriptions() is called
+    pub subscription: Option<Subscription>,
+}
+
+impl Channel {
+    fn from_json(r: &serde_json::Value) -> Result<Channel, String> {
+        // Discussions and livechat rooms have a display name only
+        let name = r["fname"]
+            .as_str()
+            .filter(|_| r["prid"].is_string() || r["t"].as_str() == Some("l"))
+            .or(r["name"].as_str())
+            .unwrap_or_default();
+
+        Ok(Channel {
+            id: String::from(r["_id"].as_str().ok_or("room _id is missing")?),
+            name: String::from(name),
+            room_type: RoomType::from_json(r),
+            parent_id: r["prid"].as_str().map(String::from),
+            num_msgs: r["msgs"].as_u64().unwrap_or(0),
+            last_message_timestamp: last_message_timestamp(r)?,
+            subscription: None,
+        })
+    }
+
+    /// Returns which endpoints manage this room
+    pub fn endpoint_family(&self) -> Option<EndpointFamily> {
+        self.room_type.endpoint_family()
+    }
+
+    /// An empty public channel, for tests
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn for_test(id: &str, name: &str) -> Channel {
+        Channel {
+            id: String::from(id),
+            name: String::from(name),
+            room_type: RoomType::Channel,
+            parent_id: None,
+            num_msgs: 0,
+            last_message_timestamp: None,
+            subscription: None,
+        }
+    }
+}
+
+impl DirectRoom {
+    fn from_json(r: &serde_json::Value) -> Result<DirectRoom, String> {
+        Ok(DirectRoom {
+            id: String::from(r["_id"].as_str().ok_or("room _id is missing")?),
+            room_type: RoomType::from_json(r),
+            num_msgs: r["msgs"].as_u64().unwrap_or(0),
+            usernames: r["usernames"]
+                .as_array()
+                .map(|usernames| {
+                    usernames
+                        .iter()
+                        .filter_map(|v| v.as_str().map(String::from))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            last_message_timestamp: last_message_timestamp(r)?,
+            subscription: None,
+        })
+    }
+}
+
+/// Either kind of room
+#[derive(Clone, Debug)]
+pub enum Room {
+    Channel(Channel),
+    Direct(DirectRoom),
+}
+
+impl Room {
+    /// Parses a room, as returned by rooms.get. Fails for unknown room types.
+    fn from_json(r: &serde_json::Value) -> Result<Room, String> {
+        match RoomType::from_json(r) {
+            RoomType::Unknown => Err(format!("unknown room type! {}", r)),
+            room_type if room_type.is_direct() => Ok(Room::Direct(DirectRoom::from_json(r)?)),
+            _ => Ok(Room::Channel(Channel::from_json(r)?)),
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        match self {
+            Room::Channel(c) => &c.id,
+            Room::Direct(d) => &d.id,
+        }
+    }
+
+    pub fn room_type(&self) -> RoomType {
+        match self {
+            Room::Channel(c) => c.room_type,
+            Room::Direct(d) => d.room_type,
+        }
+    }
+
+    /// None until list_subscriptions() is called
+    pub fn subscription(&self) -> Option<&Subscription> {
+        match self {
+            Room::Channel(c) => c.subscription.as_ref(),
+            Room::Direct(d) => d.subscription.as_ref(),
+        }
+    }
+
+    fn set_subscription(&mut self, subscription: Option<Subscription>) {
+        match self {
+            Room::Channel(c) => c.subscription = subscription,
+            Room::Direct(d) => d.subscription = subscription,
+        }
+    }
+
+    /// The channel name, or the participants of a direct conversation
+    pub fn display_name(&self) -> String {
+        match self {
+            Room::Channel(c) => c.name.clone(),
+            Room::Direct(d) => d.usernames.join(", "),
+        }
+    }
+}
+
+/// Which page of results to request
+#[derive(Clone, Copy, Debug)]
+pub struct Paging {
+    pub offset: u64,
+    pub count: u64,
+}
+
+impl Default for Paging {
+    fn default() -> Self {
+        Self {
+            offset: 0,
+            count: 50,
+        }
+    }
+}
+
+/// Why a room couldn't be created
+#[derive(Clone, Debug, PartialEq)]
+pub enum CreateRoomError {
+    /// A room with this name already exists
+    DuplicateName(String),
+    /// The name has characters the server doesn't allow
+    InvalidName(String),
+    Other(String),
+}
+
+impl CreateRoomError {
+    fn from_response(name: &str, body: &serde_json::Value) -> CreateRoomError {
+        match body["errorType"].as_str().unwrap_or_default() {
+            "error-duplicate-channel-name" => CreateRoomError::DuplicateName(name.to_string()),
+            "error-invalid-room-name" | "error-invalid-name" => {
+                CreateRoomError::InvalidName(name.to_string())
+            }
+            _ => {
+                CreateRoomError::Other(format!("failed to create {}: {}", name, redact::json(body)))
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for CreateRoomError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CreateRoomError::DuplicateName(name) => write!(f, "{} already exists", name),
+            CreateRoomError::InvalidName(name) => write!(f, "{} is not a valid name", name),
+            CreateRoomError::Other(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<String> for CreateRoomError {
+    fn from(e: String) -> Self {
+        CreateRoomError::Other(e)
+    }
+}
+
+/// A message found by a search, with the room it was posted in
+#[derive(Clone)]
+pub struct SearchResult {
+    pub message: Message,
+    pub room: Arc<Room>,
 }
 
+/// How many rooms are queried at the same time, when something needs to be fetched for every room
+const ROOM_FAN_OUT_CONCURRENCY: usize = 4;
+
 impl ExclusiveData {
     fn new(auth_token: String, user_id: String) -> Self {
         Self {
             auth_token,
             user_id,
-            joined_channels: Vec::new(),
-            direct_rooms: Vec::new(),
-            channel_rooms: Vec::new(),
+            username: String::new(),
+            highlight_words: Vec::new(),
+            mention_counters: HashMap::new(),
+            joined_channels: Arc::from([]),
+            direct_rooms: Arc::from([]),
+            channel_rooms: Arc::from([]),
+            rooms_by_id: Arc::default(),
+            subscriptions: HashMap::new(),
         }
     }
+
+    /// Replaces the rooms, copying the subscriptions into the rooms they belong to
+    /// None leaves that list alone
+    fn set_rooms(
+        &mut self,
+        joined_channels: Option<Vec<Arc<Room>>>,
+        direct_rooms: Option<Vec<Arc<Room>>>,
+        channel_rooms: Option<Vec<Arc<Room>>>,
+    ) {
+        // Only the rooms whose subscription changed are copied
+        let subscriptions = &self.subscriptions;
+        let with_subscription = |room: Arc<Room>| {
+            let subscription = subscriptions.get(room.id());
+            if room.subscription() == subscription {
+                return room;
+            }
+            let mut room = Room::clone(&room);
+            room.set_subscription(subscription.cloned());
+            Arc::new(room)
+        };
+        let update = |rooms: Option<Vec<Arc<Room>>>, current: &Arc<[Arc<Room>]>| {
+            let rooms = rooms.unwrap_or_else(|| current.to_vec());
+            rooms.into_iter().map(with_subscription).collect()
+        };
+
+        self.joined_channels = update(joined_channels, &self.joined_channels);
+        self.channel_rooms = update(channel_rooms, &self.channel_rooms);
+        self.direct_rooms = update(direct_rooms, &self.direct_rooms);
+
+        // Joined channels are also in channel_rooms, once list_rooms() was called
+        let rooms_by_id = self
+            .joined_channels
+            .iter()
+            .chain(self.channel_rooms.iter())
+            .chain(self.direct_rooms.iter())
+            .map(|room| (String::from(room.id()), room.clone()))
+            .collect();
+        self.rooms_by_id = Arc::new(rooms_by_id);
+    }
+
+    /// Copies the subscriptions into the rooms they belong to
+    fn apply_subscriptions(&mut self) {
+        self.set_rooms(None, None, None);
+    }
 }
 
 impl RocketChat {
-    pub fn new(url: &str, auth_token: &str) -> Self {
+    /// Picks up the token saved by a previous login, if any
+    pub fn new(url: &str, paths: Paths) -> Self {
+        let auth_token = Self::read_saved_auth_token(&paths);
         Self {
             url: url.to_string(),
-            exclusive_data: Mutex::new(ExclusiveData::new(auth_token.to_string(), String::new())),
+            exclusive_data: Mutex::new(ExclusiveData::new(auth_token, String::new())),
+            events: tokio::sync::broadcast::channel(event::EVENT_CAPACITY).0,
+            rate_limiter: RateLimiter::default(),
+            server_info: Mutex::new(None),
+            custom_emojis: Mutex::new(None),
+            outbox: Mutex::new(Outbox::load(paths.outbox_file())),
+            outbox_sending: tokio::sync::Mutex::new(()),
+            session_generation: tokio::sync::Mutex::new(0),
+            paths,
         }
     }
 
@@ -96,326 +401,2522 @@ impl RocketChat {
     }
 
     pub fn set_user_id(&self, id: String) {
+        if !id.is_empty() {
+            self.switch_outbox(&id);
+        }
         let mut data = self.exclusive_data.lock().unwrap();
         data.user_id = id;
     }
 
+    pub fn get_username(&self) -> String {
+        let data = self.exclusive_data.lock().unwrap();
+        data.username.clone()
+    }
+
+    /// Fetches our profile again, with our username and highlight words
+    pub async fn load_profile(&self) -> Result<(), String> {
+        let me = self.get("api/v1/me").await?;
+        if !me["success"].as_bool().unwrap_or(false) {
+            return Err(format!("me failed: {}", redact::json(&me)));
+        }
+        self.set_me(&me);
+        Ok(())
+    }
+
+    /// Remembers who we are, from the `me` object sent on login
+    fn set_me(&self, me: &serde_json::Value) {
+        let mut data = self.exclusive_data.lock().unwrap();
+        data.username = String::from(me["username"].as_str().unwrap_or_default());
+        data.highlight_words = me["settings"]["preferences"]["highlights"]
+            .as_array()
+            .map(|words| {
+                words
+                    .iter()
+                    .filter_map(|w| w.as_str().map(String::from))
+                    .collect()
+            })
+            .unwrap_or_default();
+    }
+
+    pub fn highlight_words(&self) -> Vec<String> {
+        let data = self.exclusive_data.lock().unwrap();
+        data.highlight_words.clone()
+    }
+
+    /// Saves the words that count as mentioning us to our account preferences
+    pub async fn save_highlight_words(&self, words: Vec<String>) -> Result<(), String> {
+        let body = self
+            .post_json(
+                "api/v1/users.setPreferences",
+                serde_json::json!({ "data": { "highlights": words } }),
+            )
+            .await?;
+        if !body["success"].as_bool().unwrap_or(false) {
+            return Err(format!(
+                "users.setPreferences failed: {}",
+                redact::json(&body)
+            ));
+        }
+
+        let mut data = self.exclusive_data.lock().unwrap();
+        data.highlight_words = words;
+        Ok(())
+    }
+
+    /// Returns whether a message mentions us
+    pub fn mention_kind(&self, message: &Message) -> Option<MentionKind> {
+        let data = self.exclusive_data.lock().unwrap();
+        message.mention_kind(&data.username, &data.highlight_words)
+    }
+
+    /// Counts a newly received message towards its room's mentions, and announces it
+    pub fn note_message(&self, message: &Message) -> Option<MentionKind> {
+        // The server echoing a message we queued means it was sent
+        self.set_outbox_state(&message.id, OutboxState::Sent);
+
+        let kind = self.mention_kind(message);
+        if let Some(kind) = kind {
+            let mut data = self.exclusive_data.lock().unwrap();
+            data.mention_counters
+                .entry(message.room_id.clone())
+                .or_default()
+                .add(kind);
+        }
+        self.emit(Event::MessageReceived(message.clone()));
+        if kind.is_some() {
+            if let Some(room) = self.find_room(&message.room_id) {
+                self.emit(Event::RoomUpdated(room));
+            }
+        }
+        kind
+    }
+
+    /// Returns the unread mentions in a room
+    /// Combines what the subscription reported with what note_message() saw since
+    pub fn mention_counters(&self, room_id: &str) -> MentionCounters {
+        let data = self.exclusive_data.lock().unwrap();
+        let mut counters = data
+            .mention_counters
+            .get(room_id)
+            .copied()
+            .unwrap_or_default();
+        if let Some(subscription) = data.subscriptions.get(room_id) {
+            counters.direct += subscription.user_mentions;
+            counters.group += subscription.group_mentions;
+        }
+        counters
+    }
+
+    /// Forgets the mentions we counted in a room, once it has been read
+    pub fn clear_mentions(&self, room_id: &str) {
+        let removed = {
+            let mut data = self.exclusive_data.lock().unwrap();
+            data.mention_counters.remove(room_id).is_some()
+        };
+        if removed {
+            if let Some(room) = self.find_room(room_id) {
+                self.emit(Event::RoomUpdated(room));
+            }
+        }
+    }
+
+    /// Sets our presence, as seen by everyone else
+    pub async fn set_status(&self, status: UserStatus, message: &str) -> Result<(), String> {
+        let mut map = HashMap::new();
+        map.insert("status", status.as_str());
+        map.insert("message", message);
+        let body = self.post("api/v1/users.setStatus", map).await?;
+        if !body["success"].as_bool().unwrap_or(false) {
+            return Err(format!("users.setStatus failed: {}", redact::json(&body)));
+        }
+
+        self.emit(Event::PresenceChanged {
+            user_id: self.get_user_id(),
+            status,
+        });
+        Ok(())
+    }
+
     fn clear_user_id(&self) {
         let mut data = self.exclusive_data.lock().unwrap();
         data.user_id.clear();
     }
 
+    /// Forgets the credentials and every room we know about
+    fn clear_session(&self) {
+        let mut data = self.exclusive_data.lock().unwrap();
+        *data = ExclusiveData::default();
+    }
+
     pub fn set_joined_channels(&self, channels: Vec<Channel>) {
         let mut data = self.exclusive_data.lock().unwrap();
-        data.joined_channels = channels;
+        data.set_rooms(Some(shared_rooms(channels, Room::Channel)), None, None);
+        drop(data);
+        self.emit(Event::RoomsReset);
     }
 
     pub fn set_direct_rooms(&self, rooms: Vec<DirectRoom>) {
         let mut data = self.exclusive_data.lock().unwrap();
-        data.direct_rooms = rooms;
+        data.set_rooms(None, Some(shared_rooms(rooms, Room::Direct)), None);
+        drop(data);
+        self.emit(Event::RoomsReset);
     }
 
     pub fn set_channel_rooms(&self, rooms: Vec<Channel>) {
         let mut data = self.exclusive_data.lock().unwrap();
-        data.channel_rooms = rooms;
+        data.set_rooms(None, None, Some(shared_rooms(rooms, Room::Channel)));
+        drop(data);
+        self.emit(Event::RoomsReset);
     }
 
-    pub fn get_joined_channels(&self) -> Vec<Channel> {
+    /// Every one is a Room::Channel
+    pub fn get_joined_channels(&self) -> Arc<[Arc<Room>]> {
         let data = self.exclusive_data.lock().unwrap();
         data.joined_channels.clone()
     }
 
-    pub fn get_direct_rooms(&self) -> Vec<DirectRoom> {
+    /// Every one is a Room::Direct
+    pub fn get_direct_rooms(&self) -> Arc<[Arc<Room>]> {
         let data = self.exclusive_data.lock().unwrap();
         data.direct_rooms.clone()
     }
 
-    pub fn get_channel_rooms(&self) -> Vec<Channel> {
+    /// Every one is a Room::Channel
+    pub fn get_channel_rooms(&self) -> Arc<[Arc<Room>]> {
         let data = self.exclusive_data.lock().unwrap();
         data.channel_rooms.clone()
     }
 
-    // TODO: Improve error handling
-    fn config_path(ensure_created: bool) -> String {
-        let dir = dirs_next::config_dir().expect("Couldn't find config dir");
-        let dir = dir.to_str().expect("Couldn't find config dir");
-        let rc_config_dir = if cfg!(test) {
-            format!("{}/rc-slint/tests", dir)
-        } else {
-            format!("{}/rc-slint", dir)
-        };
+    /// Adds a room we just joined or created to the local state
+    fn add_room(&self, room: Room) {
+        let room = self.store_room(room);
+        self.emit(Event::RoomAdded(room));
+    }
 
-        if ensure_created {
-            std::fs::create_dir_all(&rc_config_dir).expect("Couldn't create config dir");
+    /// Adds a room to the lists it belongs in, or replaces it if we have it already
+    fn store_room(&self, room: Room) -> Arc<Room> {
+        let room_id = String::from(room.id());
+        let room = Arc::new(room);
+        let mut data = self.exclusive_data.lock().unwrap();
+        let without = |rooms: &[Arc<Room>]| -> Vec<Arc<Room>> {
+            rooms
+                .iter()
+                .filter(|r| r.id() != room_id)
+                .cloned()
+                .collect()
+        };
+        match &*room {
+            Room::Channel(channel) => {
+                let mut channel_rooms = without(&data.channel_rooms);
+                let joined_channels = if channel.room_type == RoomType::Channel {
+                    let mut joined_channels = without(&data.joined_channels);
+                    joined_channels.push(room.clone());
+                    Some(joined_channels)
+                } else {
+                    None
+                };
+                channel_rooms.push(room.clone());
+                data.set_rooms(joined_channels, None, Some(channel_rooms));
+            }
+            Room::Direct(_) => {
+                let mut direct_rooms = without(&data.direct_rooms);
+                direct_rooms.push(room.clone());
+                data.set_rooms(None, Some(direct_rooms), None);
+            }
         }
-
-        rc_config_dir.to_string()
+        // With our subscription copied in
+        data.rooms_by_id[&room_id].clone()
     }
 
-    fn auto_token_path() -> String {
-        format!("{}/.auth_token", Self::config_path(true))
+    /// Applies a change to our rooms, as reported by Realtime::subscribe_room_changes()
+    /// Rooms we were just added to are fetched with rooms.info
+    pub async fn apply_room_change(&self, change: RoomChange) -> Result<(), String> {
+        match change {
+            RoomChange::Subscribed(subscription) => {
+                let room_id = subscription.room_id.clone();
+                let room = {
+                    let mut data = self.exclusive_data.lock().unwrap();
+                    data.subscriptions.insert(room_id.clone(), subscription);
+                    data.apply_subscriptions();
+                    data.rooms_by_id.get(&room_id).cloned()
+                };
+                match room {
+                    Some(room) => self.emit(Event::RoomUpdated(room)),
+                    None => {
+                        let room = self.fetch_room(&room_id).await?;
+                        self.add_room(room);
+                    }
+                }
+            }
+            RoomChange::Updated(room) => {
+                if self.find_room(room.id()).is_some() {
+                    let room = self.store_room(room);
+                    self.emit(Event::RoomUpdated(room));
+                } else {
+                    self.add_room(room);
+                }
+            }
+            // Unless we left through leave_room(), which removed it already
+            RoomChange::Left(room_id) => {
+                if self.find_room(&room_id).is_some() {
+                    self.remove_room(&room_id);
+                }
+            }
+        }
+        Ok(())
     }
 
-    pub fn saved_auth_token() -> String {
-        let path = Self::auto_token_path();
-        if let Ok(token) = std::fs::read_to_string(path) {
-            token
-        } else {
-            "".to_string()
+    /// Fetches a room by ID, without adding it to the local state
+    async fn fetch_room(&self, room_id: &str) -> Result<Room, String> {
+        let body = self
+            .get(&with_query("api/v1/rooms.info", &[("roomId", room_id)]))
+            .await?;
+        if !body["success"].as_bool().unwrap_or(false) {
+            return Err(format!("rooms.info failed: {}", redact::json(&body)));
         }
+        Room::from_json(&body["room"])
     }
 
-    fn save_auth_token(&self, token: &str) -> Result<(), std::io::Error> {
+    /// Removes a room we left from the local state
+    fn remove_room(&self, room_id: &str) {
         {
             let mut data = self.exclusive_data.lock().unwrap();
-            data.auth_token = token.to_string();
+            let without = |rooms: &[Arc<Room>]| -> Vec<Arc<Room>> {
+                rooms
+                    .iter()
+                    .filter(|r| r.id() != room_id)
+                    .cloned()
+                    .collect()
+            };
+            let joined_channels = without(&data.joined_channels);
+            let direct_rooms = without(&data.direct_rooms);
+            let channel_rooms = without(&data.channel_rooms);
+            data.set_rooms(
+                Some(joined_channels),
+                Some(direct_rooms),
+                Some(channel_rooms),
+            );
         }
-
-        let path = Self::auto_token_path();
-        println!("Saving token to {:?}", path);
-        std::fs::write(path, token)
+        self.emit(Event::RoomRemoved(String::from(room_id)));
     }
 
-    /// Sends a POST request
-    pub async fn post(
-        &self,
-        endpoint: &str,
-        data: HashMap<&str, &str>,
-    ) -> Result<serde_json::Value, String> {
-        let client = reqwest::Client::new();
-        let url = format!("{}/{}", self.url, endpoint);
-        let res = client
-            .post(&url)
-            .json(&data)
-            .send()
-            .await
-            .map_err(|e| e.to_string())?
-            .text()
-            .await;
+    /// Returns which endpoints manage a room
+    /// This depends on the room type, so the room needs to be known already
+    fn room_endpoint_family(&self, room_id: &str) -> Result<EndpointFamily, String> {
+        self.find_room(room_id)
+            .ok_or(format!("unknown room {}", room_id))?
+            .room_type()
+            .endpoint_family()
+            .ok_or(format!("{} isn't a channel, group or direct room", room_id))
+    }
 
-        if let Ok(body) = res {
-            // println!("url = {:?} ; Post.body = {:?}", url, body);
-            let json: serde_json::Value = serde_json::from_str(&body).unwrap();
-            Result::Ok(json)
-        } else {
-            println!("Post failed: {:?}", res);
-            Result::Err("Post failed".to_string())
-        }
+    /// Returns the endpoint for an operation on a room, for example "api/v1/groups.members"
+    fn room_endpoint(&self, room_id: &str, method: &str) -> Result<String, String> {
+        let family = self.room_endpoint_family(room_id)?;
+        Ok(format!("api/v1/{}.{}", family.as_str(), method))
     }
 
-    /// Sends a GET request
-    /// TODO: handle errors
-    pub async fn get(&self, endpoint: &str) -> Result<serde_json::Value, ()> {
-        let client = reqwest::Client::new();
-        let res = client
-            .get(&format!("{}/{}", self.url, endpoint))
-            .header("X-Auth-Token", self.get_auth_token())
-            .header("X-User-Id", self.get_user_id().as_str())
-            .send()
-            .await
-            .unwrap()
-            .text()
-            .await;
+    /// Returns our subscription to a room, if list_subscriptions() was called
+    pub fn subscription(&self, room_id: &str) -> Option<Subscription> {
+        let data = self.exclusive_data.lock().unwrap();
+        data.subscriptions.get(room_id).cloned()
+    }
 
-        if let Ok(body) = res {
-            // println!("Get.body = {:?}", body);
-            let json: serde_json::Value = serde_json::from_str(&body).unwrap();
-            Result::Ok(json)
-        } else {
-            Result::Err(())
+    /// Changes a subscription we know about, after the server accepted the change
+    fn update_subscription<F>(&self, room_id: &str, f: F)
+    where
+        F: FnOnce(&mut Subscription),
+    {
+        {
+            let mut data = self.exclusive_data.lock().unwrap();
+            match data.subscriptions.get_mut(room_id) {
+                Some(subscription) => f(subscription),
+                None => return,
+            }
+            data.apply_subscriptions();
+        }
+        if let Some(room) = self.find_room(room_id) {
+            self.emit(Event::RoomUpdated(room));
         }
     }
 
-    /// Logs in via a pre-existing token
-    pub async fn login_via_saved_token(&self) -> Result<bool, String> {
-        self.clear_user_id();
-        // println!("login_via_token: auth_token = {:?}", self.get_auth_token());
-        if self.get_auth_token().is_empty() {
-            // No error. But we can't login without a token.
-            return Ok(false);
-        }
+    /// Looks up a room we know about, by ID
+    pub fn find_room(&self, room_id: &str) -> Option<Arc<Room>> {
+        self.rooms().get(room_id).cloned()
+    }
 
-        let mut map = HashMap::new();
-        let auth_token = self.get_auth_token();
-        map.insert("resume", auth_token.as_str());
-        let json = self.post("api/v1/login", map).await?;
+    /// Returns every room we know about, by ID
+    pub fn rooms(&self) -> Arc<HashMap<String, Arc<Room>>> {
+        let data = self.exclusive_data.lock().unwrap();
+        data.rooms_by_id.clone()
+    }
 
-        // println!("login_via_token: {:?}", json);
-        let success = json["status"].as_str().ok_or("status is missing")? == "success";
-        if success {
-            self.set_user_id(String::from(
-                json["data"]["userId"]
-                    .as_str()
-                    .ok_or("data or userId is missing")?,
-            ));
-        }
+    pub fn paths(&self) -> &Paths {
+        &self.paths
+    }
 
-        Ok(success)
+    fn read_saved_auth_token(paths: &Paths) -> String {
+        std::fs::read_to_string(paths.auth_token_file()).unwrap_or_default()
     }
 
-    pub async fn login(&self, user: &str, pwd: &str) -> Result<(), String> {
-        let mut map = HashMap::new();
+    pub fn saved_auth_token(&self) -> String {
+        Self::read_saved_auth_token(&self.paths)
+    }
 
-        self.login_via_saved_token().await?;
-        if self.is_logged_in() {
-            return Ok(());
+    fn save_auth_token(&self, token: &str) -> Result<(), String> {
+        {
+            let mut data = self.exclusive_data.lock().unwrap();
+            data.auth_token = token.to_string();
         }
 
-        self.save_auth_token("").unwrap();
-
-        map.insert("user", user);
-        map.insert("password", pwd);
-
-        // println!("map = {:?}", map);
-        let json = self.post("api/v1/login", map).await?;
-        // println!("body = {:?}", json);
+        paths::ensure_created(&self.paths.config_dir)?;
+        let path = self.paths.auth_token_file();
+        log::debug!("Saving token to {:?}", path);
+        std::fs::write(&path, token)
+            .map_err(|e| format!("couldn't save the token to {}: {}", path.display(), e))
+    }
 
-        let success = json["status"].as_str().ok_or("status is missing")? == "success";
-        if !success {
-            return Err(String::from("login failed, success=false"));
+    /// Deletes the token saved by a previous login, if any
+    fn remove_saved_auth_token(&self) -> Result<(), String> {
+        let path = self.paths.auth_token_file();
+        match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(format!("couldn't remove {}: {}", path.display(), e))
+            }
+            _ => Ok(()),
         }
+    }
 
-        self.set_user_id(String::from(
-            json["data"]["userId"]
-                .as_str()
-                .ok_or("data or userId is missing")?,
-        ));
+    /// Returns the rate-limit budget the server last reported for an endpoint
+    /// None if the endpoint wasn't called yet or isn't rate limited
+    pub fn rate_limit(&self, endpoint: &str) -> Option<RateLimit> {
+        self.rate_limiter.get(rate_limit::key(endpoint))
+    }
 
-        if self.is_logged_in() {
-            self.set_auth_token(String::from(
-                json["data"]["authToken"]
-                    .as_str()
-                    .ok_or("data or authToken is missing")?,
-            ));
-            println!("login success. authToken: {:?}", self.get_auth_token());
-            self.save_auth_token(self.get_auth_token().as_str())
-                .unwrap();
-            Ok(())
-        } else {
-            println!("login failed: {:?}", json);
-            Err(String::from("login failed"))
-        }
+    /// Returns a receiver for every change to our local state, from now on
+    /// Receivers that fall behind by more than a few hundred events miss the oldest ones
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<Event> {
+        self.events.subscribe()
     }
 
-    pub async fn list_joined_channels(&self) {
-        if !self.is_logged_in() {
-            panic!("not logged in");
-        }
-        let res = self.get("/api/v1/channels.list.joined").await;
-        match res {
-            Ok(body) => {
-                // serde_json::to_writer_pretty(std::io::stdout(), &body).unwrap();
-                let channels = body["channels"].as_array().unwrap();
-                let mut joined_channels = Vec::new();
-                for c in channels {
-                    let channel = Channel {
-                        id: String::from(c["_id"].as_str().unwrap()),
-                        name: String::from(c["name"].as_str().unwrap()),
-                        num_msgs: c["msgs"].as_u64().unwrap(),
-                        last_message_timestamp: Some(str_to_timestamp(c["lm"].as_str())),
-                    };
-
-                    joined_channels.push(channel);
-                }
-                self.set_joined_channels(joined_channels);
-            }
-            Err(_) => todo!(),
-        }
+    fn emit(&self, event: Event) {
+        // Fails only if nobody is listening, which is fine
+        let _ = self.events.send(event);
     }
 
-    pub async fn list_rooms(&self) {
-        if !self.is_logged_in() {
-            panic!("not logged in");
-        }
-        let res = self.get("/api/v1/rooms.get").await;
-        match res {
-            Ok(body) => {
-                // serde_json::to_writer_pretty(std::io::stdout(), &body).unwrap();
-                let success = body["success"].as_bool().unwrap_or(false);
-                if !success {
-                    println!("list_rooms: Failed to list rooms!");
-                    return;
+    /// Sends a request, waiting for the rate limit and retrying what's safe to retry
+    /// Logs the request to HTTP_LOG_TARGET. Returns the response, whatever its status.
+    async fn send(
+        &self,
+        method: reqwest::Method,
+        endpoint: &str,
+        payload: &Payload<'_>,
+        credentials: Credentials,
+    ) -> Result<reqwest::Response, String> {
+        // Endpoints are relative to the server URL, which has no trailing slash
+        let endpoint = endpoint.trim_start_matches('/');
+        let client = reqwest::Client::new();
+        let url = format!("{}/{}", self.url, endpoint);
+        let rate_limit_key = rate_limit::key(endpoint);
+        let idempotent = method == reqwest::Method::GET;
+
+        let mut attempt = 0;
+        loop {
+            self.rate_limiter.acquire(rate_limit_key).await;
+
+            let mut request = payload.attach(client.request(method.clone(), &url));
+            if self.is_logged_in() {
+                match credentials {
+                    Credentials::Headers => {
+                        request = request
+                            .header("X-Auth-Token", self.get_auth_token())
+                            .header("X-User-Id", self.get_user_id().as_str());
+                    }
+                    // File routes check cookies rather than the REST API's headers
+                    Credentials::Cookie => {
+                        request = request.header(
+                            reqwest::header::COOKIE,
+                            format!(
+                                "rc_uid={}; rc_token={}",
+                                self.get_user_id(),
+                                self.get_auth_token()
+                            ),
+                        );
+                    }
+                    Credentials::None => {}
                 }
+            }
 
-                let mut direct_rooms = Vec::new();
-                let mut channel_rooms = Vec::new();
-
-                let rooms = body["update"].as_array().unwrap();
-                for r in rooms {
-                    let room_type = RoomType::from_str(r["t"].as_str().unwrap_or(""));
-                    match room_type {
-                        RoomType::Direct => {
-                            direct_rooms.push(DirectRoom {
-                                id: String::from(r["_id"].as_str().unwrap()),
-                                num_msgs: r["msgs"].as_u64().unwrap_or(0),
-                                usernames: r["usernames"]
-                                    .as_array()
-                                    .unwrap()
-                                    .iter()
-                                    .map(|v| String::from(v.as_str().unwrap()))
-                                    .collect(),
-                                last_message_timestamp: Some(str_to_timestamp(r["lm"].as_str())),
-                            });
-                        }
-                        RoomType::Unknown => {
-                            println!("unknown room type! {} ", r);
-                        }
-                        RoomType::Channel => {
-                            channel_rooms.push(Channel {
-                                id: String::from(r["_id"].as_str().unwrap()),
-                                name: String::from(r["name"].as_str().unwrap()),
-                                num_msgs: r["msgs"].as_u64().unwrap_or(0),
-                                last_message_timestamp: Some(str_to_timestamp(r["lm"].as_str())),
-                            });
-                        }
+            if log::log_enabled!(target: HTTP_LOG_TARGET, log::Level::Trace) {
+                log::trace!(
+                    target: HTTP_LOG_TARGET,
+                    "--> {} {} X-User-Id: {} X-Auth-Token: {} {}",
+                    method,
+                    endpoint,
+                    self.get_user_id(),
+                    redact::header("X-Auth-Token", &self.get_auth_token()),
+                    payload.describe()
+                );
+            }
+
+            let result = request.send().await;
+            let retry_delay = match &result {
+                Ok(response) => {
+                    if let Some(limit) = RateLimit::from_headers(response.headers()) {
+                        self.rate_limiter.update(rate_limit_key, limit);
+                    }
+
+                    if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+                        Some(self.rate_limiter.retry_delay(rate_limit_key, attempt))
+                    } else if response.status().is_server_error() {
+                        Some(rate_limit::backoff_delay(attempt))
+                    } else {
+                        None
                     }
                 }
+                Err(_) => Some(rate_limit::backoff_delay(attempt)),
+            };
 
-                self.set_direct_rooms(direct_rooms);
-                self.set_channel_rooms(channel_rooms);
+            match retry_delay {
+                Some(delay) if idempotent && attempt < rate_limit::MAX_RETRIES => {
+                    log::warn!("{} failed, retrying in {:?}", endpoint, delay);
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                _ => return result.map_err(|e| e.to_string()),
             }
-            Err(_) => todo!(),
         }
     }
-}
 
-// example input: 2022-05-17T14:55:23.276Z
-fn str_to_timestamp(s: Option<&str>) -> i64 {
-    if s.is_none() {
-        return -1;
-    }
-    let s = s.unwrap();
-    let mut parts = s.split('T');
-    let date = parts.next().unwrap();
-    let time = parts.next().unwrap();
-    let mut parts = time.split('.');
-    let time = parts.next().unwrap();
-    let mut parts = time.split(':');
-    let hour = parts.next().unwrap().parse::<i64>().unwrap();
-    let min = parts.next().unwrap().parse::<i64>().unwrap();
-    let sec = parts.next().unwrap().parse::<i64>().unwrap();
-    let mut parts = date.split('-');
-    let year = parts.next().unwrap().parse::<i32>().unwrap();
-    let month = parts.next().unwrap().parse::<u32>().unwrap();
-    let day = parts.next().unwrap().parse::<u32>().unwrap();
-    let dt = chrono::Utc.with_ymd_and_hms(year, month, day, hour as u32, min as u32, sec as u32);
-    dt.unwrap().timestamp()
-}
-
-#[cfg(test)]
-mod tests {
+    /// Sends a single REST request, with the credentials if we have them
+    /// Returns the status, along with the body
+    /// Without `authenticated`, the request goes out without our credentials
+    async fn request_once(
+        &self,
+        method: reqwest::Method,
+        endpoint: &str,
+        payload: &Payload<'_>,
+        authenticated: bool,
+    ) -> Result<(reqwest::StatusCode, serde_json::Value), String> {
+        let credentials = if authenticated {
+            Credentials::Headers
+        } else {
+            Credentials::None
+        };
+        let response = self.send(method, endpoint, payload, credentials).await?;
+
+        let status = response.status();
+        let body = response.text().await.map_err(|e| e.to_string())?;
+        let json: serde_json::Value = serde_json::from_str(&body).map_err(|e| {
+            log::trace!(target: HTTP_LOG_TARGET, "<-- {} {} ({} bytes)", status, endpoint, body.len());
+            format!("{} returned invalid json ({}): {}", endpoint, status, e)
+        })?;
+        if log::log_enabled!(target: HTTP_LOG_TARGET, log::Level::Trace) {
+            log::trace!(
+                target: HTTP_LOG_TARGET,
+                "<-- {} {} {}",
+                status,
+                endpoint,
+                redact::json(&json)
+            );
+        }
+
+        Ok((status, json))
+    }
+
+    /// Sends a request, logging in again with the stored token if the session expired
+    async fn request(
+        &self,
+        method: reqwest::Method,
+        endpoint: &str,
+        data: Option<&serde_json::Value>,
+    ) -> Result<serde_json::Value, String> {
+        let payload = data.map_or(Payload::Empty, Payload::Json);
+        self.request_with_status(method, endpoint, &payload)
+            .await
+            .map(|(_, json)| json)
+    }
+
+    /// Like request(), for callers that need to tell rate limiting and the like apart
+    async fn request_with_status(
+        &self,
+        method: reqwest::Method,
+        endpoint: &str,
+        payload: &Payload<'_>,
+    ) -> Result<(reqwest::StatusCode, serde_json::Value), String> {
+        self.with_session_recovery(
+            endpoint,
+            || self.request_once(method.clone(), endpoint, payload, true),
+            |(status, json)| is_unauthorized(*status, json),
+        )
+        .await
+    }
+
+    /// Runs `attempt`, and if it was `rejected` for our credentials, resumes the session and
+    /// runs it again
+    /// Concurrent requests rejected at the same time share a single resume attempt.
+    async fn with_session_recovery<T, F, Fut>(
+        &self,
+        endpoint: &str,
+        attempt: F,
+        rejected: fn(&T) -> bool,
+    ) -> Result<T, String>
+    where
+        F: Fn() -> Fut,
+        Fut: std::future::Future<Output = Result<T, String>>,
+    {
+        let was_logged_in = self.is_logged_in();
+        let generation = *self.session_generation.lock().await;
+        let result = attempt().await?;
+        if !rejected(&result) || !was_logged_in {
+            return Ok(result);
+        }
+
+        let mut current = self.session_generation.lock().await;
+        if *current == generation {
+            if !self.is_logged_in() {
+                // Someone else's resume failed already
+                return Err(String::from("session expired"));
+            }
+
+            log::info!("{} was rejected, trying to resume the session", endpoint);
+            if !self.resume_session().await {
+                self.expire_session();
+                return Err(String::from("session expired"));
+            }
+            *current += 1;
+        }
+        drop(current);
+
+        let result = attempt().await?;
+        if rejected(&result) {
+            return Err(format!(
+                "{} was rejected after resuming the session",
+                endpoint
+            ));
+        }
+        Ok(result)
+    }
+
+    /// Logs in again with the token saved on disk, or the one we have in memory
+    /// Callers hold session_generation, so only one resume runs at a time.
+    async fn resume_session(&self) -> bool {
+        let saved_token = self.saved_auth_token();
+        if !saved_token.is_empty() {
+            self.set_auth_token(saved_token);
+        }
+
+        // Can't use login_via_saved_token(), as it can recurse into request()
+        let auth_token = self.get_auth_token();
+        let mut map = HashMap::new();
+        map.insert("resume", auth_token.as_str());
+        match self.login_request(map).await {
+            Ok(json) if json["status"].as_str() == Some("success") => {
+                match json["data"]["userId"].as_str() {
+                    Some(user_id) => {
+                        self.set_user_id(String::from(user_id));
+                        if let Some(token) = json["data"]["authToken"].as_str() {
+                            if let Err(e) = self.save_auth_token(token) {
+                                log::warn!("{}", e);
+                            }
+                        }
+                        true
+                    }
+                    None => false,
+                }
+            }
+            _ => false,
+        }
+    }
+
+    fn expire_session(&self) {
+        log::warn!("Session expired");
+        self.clear_session();
+        self.emit(Event::LoginChanged(LoginState::SessionExpired));
+    }
+
+    /// Posts to the login endpoint. Doesn't attempt any session recovery.
+    async fn login_request(&self, data: HashMap<&str, &str>) -> Result<serde_json::Value, String> {
+        let (_, json) = self
+            .request_once(
+                reqwest::Method::POST,
+                "api/v1/login",
+                &Payload::Json(&serde_json::json!(data)),
+                false,
+            )
+            .await?;
+        Ok(json)
+    }
+
+    /// Sends a POST request
+    pub async fn post(
+        &self,
+        endpoint: &str,
+        data: HashMap<&str, &str>,
+    ) -> Result<serde_json::Value, String> {
+        self.post_json(endpoint, serde_json::json!(data)).await
+    }
+
+    /// Sends a POST request, for payloads that aren't just strings
+    pub async fn post_json(
+        &self,
+        endpoint: &str,
+        data: serde_json::Value,
+    ) -> Result<serde_json::Value, String> {
+        self.request(reqwest::Method::POST, endpoint, Some(&data))
+            .await
+    }
+
+    /// Sends a GET request
+    /// Retried with backoff if rate limited or if the server is having trouble
+    pub async fn get(&self, endpoint: &str) -> Result<serde_json::Value, String> {
+        self.request(reqwest::Method::GET, endpoint, None).await
+    }
+
+    /// Returns the server version and what it supports
+    /// Only fetched once, subsequent calls return the cached result
+    pub async fn server_info(&self) -> Result<ServerInfo, String> {
+        if let Some(server_info) = self.cached_server_info() {
+            return Ok(server_info);
+        }
+
+        let info = self.get("api/info").await?;
+        if !info["success"].as_bool().unwrap_or(false) {
+            return Err(format!("api/info failed: {}", redact::json(&info)));
+        }
+
+        let settings = self
+            .get(&format!(
+                "api/v1/settings.public?_id={}&count={}",
+                server_info::PUBLIC_SETTINGS.join(","),
+                server_info::PUBLIC_SETTINGS.len()
+            ))
+            .await?;
+        if !settings["success"].as_bool().unwrap_or(false) {
+            return Err(format!(
+                "settings.public failed: {}",
+                redact::json(&settings)
+            ));
+        }
+
+        let server_info = ServerInfo::from_json(&info, &settings);
+        *self.server_info.lock().unwrap() = Some(server_info.clone());
+        Ok(server_info)
+    }
+
+    /// Returns the server info if server_info() was already called
+    pub fn cached_server_info(&self) -> Option<ServerInfo> {
+        self.server_info.lock().unwrap().clone()
+    }
+
+    /// Returns the emojis uploaded to the server
+    /// Only fetched once, subsequent calls return the cached result
+    pub async fn custom_emojis(&self) -> Result<Vec<CustomEmoji>, String> {
+        if let Some(emojis) = self.custom_emojis.lock().unwrap().clone() {
+            return Ok(emojis);
+        }
+
+        let json = self.get("api/v1/emoji-custom.list").await?;
+        if !json["success"].as_bool().unwrap_or(false) {
+            return Err(format!("emoji-custom.list failed: {}", redact::json(&json)));
+        }
+
+        let emojis = json["emojis"]["update"]
+            .as_array()
+            .ok_or("emojis is missing")?
+            .iter()
+            .map(CustomEmoji::from_json)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        *self.custom_emojis.lock().unwrap() = Some(emojis.clone());
+        Ok(emojis)
+    }
+
+    /// Resolves a shortcode, without the colons, into a standard or custom emoji
+    /// Custom emojis are only known after custom_emojis() was called
+    pub fn resolve_emoji(&self, shortcode: &str) -> Option<Emoji> {
+        if let Some(unicode) = emoji::unicode_for(shortcode) {
+            return Some(Emoji::Unicode(unicode));
+        }
+
+        self.custom_emojis
+            .lock()
+            .unwrap()
+            .as_ref()?
+            .iter()
+            .find(|e| e.matches(shortcode))
+            .map(|e| Emoji::Custom(e.clone()))
+    }
+
+    /// Returns the local path of a custom emoji's image, downloading it if needed
+    pub async fn custom_emoji_image(&self, emoji: &CustomEmoji) -> Result<PathBuf, String> {
+        self.download(&emoji.image_path()).await
+    }
+
+    /// Downloads a file served by the server, or returns the cached copy
+    /// `path` is relative to the server URL
+    pub async fn download(&self, path: &str) -> Result<PathBuf, String> {
+        let file_name: String = path
+            .trim_start_matches('/')
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let local_path = paths::ensure_created(&self.paths.cache_dir)?.join(file_name);
+        if local_path.exists() {
+            return Ok(local_path);
+        }
+
+        let (status, bytes) = self
+            .with_session_recovery(
+                path,
+                || self.fetch_file(path),
+                |(status, _)| *status == reqwest::StatusCode::UNAUTHORIZED,
+            )
+            .await?;
+        if !status.is_success() {
+            return Err(format!("Downloading {} failed: {}", path, status));
+        }
+
+        std::fs::write(&local_path, bytes).map_err(|e| e.to_string())?;
+        Ok(local_path)
+    }
+
+    /// Fetches a file once, returning the status along with the contents
+    async fn fetch_file(&self, path: &str) -> Result<(reqwest::StatusCode, Vec<u8>), String> {
+        let response = self
+            .send(
+                reqwest::Method::GET,
+                path,
+                &Payload::Empty,
+                Credentials::Cookie,
+            )
+            .await?;
+        let status = response.status();
+        let bytes = response.bytes().await.map_err(|e| e.to_string())?;
+        log::trace!(target: HTTP_LOG_TARGET, "<-- {} {} ({} bytes)", status, path, bytes.len());
+        Ok((status, bytes.to_vec()))
+    }
+
+    /// Opens a realtime connection, logged in as us
+    pub async fn realtime(&self) -> Result<Realtime, String> {
+        if !self.is_logged_in() {
+            return Err(String::from("realtime: not logged in"));
+        }
+        Realtime::connect(&self.url, &self.get_auth_token()).await
+    }
+
+    /// Follows our rooms, their messages and everyone's presence over the realtime API, as events
+    /// Reconnects after `reconnect_delay` whenever the connection drops, returns once logged out.
+    pub async fn follow_realtime(&self, reconnect_delay: Duration) {
+        realtime::Follower::new(self).run(reconnect_delay).await
+    }
+
+    /// Logs in via a pre-existing token
+    pub async fn login_via_saved_token(&self) -> Result<bool, String> {
+        self.clear_user_id();
+        if self.get_auth_token().is_empty() {
+            // No error. But we can't login without a token.
+            return Ok(false);
+        }
+
+        let mut map = HashMap::new();
+        let auth_token = self.get_auth_token();
+        map.insert("resume", auth_token.as_str());
+        let json = self.login_request(map).await?;
+
+        let success = json["status"].as_str().ok_or("status is missing")? == "success";
+        if success {
+            self.set_user_id(String::from(
+                json["data"]["userId"]
+                    .as_str()
+                    .ok_or("data or userId is missing")?,
+            ));
+            self.set_me(&json["data"]["me"]);
+            self.emit(Event::LoginChanged(LoginState::LoggedIn));
+        }
+
+        Ok(success)
+    }
+
+    pub async fn login(&self, user: &str, pwd: &str) -> Result<(), String> {
+        let mut map = HashMap::new();
+
+        self.login_via_saved_token().await?;
+        if self.is_logged_in() {
+            return Ok(());
+        }
+
+        self.save_auth_token("")?;
+
+        map.insert("user", user);
+        map.insert("password", pwd);
+
+        let json = self.login_request(map).await?;
+
+        let success = json["status"].as_str().ok_or("status is missing")? == "success";
+        if !success {
+            return Err(String::from("login failed, success=false"));
+        }
+
+        self.set_user_id(String::from(
+            json["data"]["userId"]
+                .as_str()
+                .ok_or("data or userId is missing")?,
+        ));
+        self.set_me(&json["data"]["me"]);
+
+        if self.is_logged_in() {
+            self.set_auth_token(String::from(
+                json["data"]["authToken"]
+                    .as_str()
+                    .ok_or("data or authToken is missing")?,
+            ));
+            log::info!("Logged in as {}", self.get_username());
+            self.save_auth_token(self.get_auth_token().as_str())?;
+            self.emit(Event::LoginChanged(LoginState::LoggedIn));
+            Ok(())
+        } else {
+            log::warn!("login failed: {}", redact::json(&json));
+            Err(String::from("login failed"))
+        }
+    }
+
+    /// Logs out, invalidating the token on the server and locally
+    /// The local session is cleared even if the server can't be reached
+    pub async fn logout(&self) -> Result<(), String> {
+        let result = if self.is_logged_in() {
+            // No session recovery: resuming only to log out makes no sense
+            self.request_once(
+                reqwest::Method::POST,
+                "api/v1/logout",
+                &Payload::Json(&serde_json::json!({})),
+                true,
+            )
+            .await
+            .and_then(|(status, json)| {
+                if json["status"].as_str() == Some("success") || is_unauthorized(status, &json) {
+                    // A rejected token means we're logged out already
+                    Ok(())
+                } else {
+                    Err(format!("logout failed: {}", redact::json(&json)))
+                }
+            })
+        } else {
+            Ok(())
+        };
+
+        // Kept for when we log back in
+        self.switch_outbox("");
+        self.clear_session();
+        self.emit(Event::LoginChanged(LoginState::LoggedOut));
+        self.remove_saved_auth_token()?;
+
+        result
+    }
+
+    /// Runs `f` for every room we're in, a few rooms at a time, and concatenates the results
+    /// Rooms for which `f` fails are skipped
+    async fn fan_out_over_rooms<'a, T, F, Fut>(&'a self, caller: &str, f: F) -> Vec<T>
+    where
+        F: Fn(String) -> Fut,
+        Fut: std::future::Future<Output = Result<Vec<T>, String>> + 'a,
+    {
+        let room_ids: Vec<String> = {
+            let data = self.exclusive_data.lock().unwrap();
+            data.channel_rooms
+                .iter()
+                .chain(data.direct_rooms.iter())
+                .map(|room| String::from(room.id()))
+                .collect()
+        };
+
+        // Collected before filtering, an async filter_map would make the future !Send
+        let results: Vec<Result<Vec<T>, String>> = futures::stream::iter(room_ids)
+            .map(f)
+            .buffer_unordered(ROOM_FAN_OUT_CONCURRENCY)
+            .collect()
+            .await;
+
+        results
+            .into_iter()
+            .filter_map(|result| match result {
+                Ok(results) => Some(results),
+                Err(e) => {
+                    log::warn!("{}: {}", caller, e);
+                    None
+                }
+            })
+            .flatten()
+            .collect()
+    }
+
+    /// Searches the messages of a room
+    /// The room needs to be known already, see list_rooms()
+    pub async fn search_messages(
+        &self,
+        room_id: &str,
+        query: &str,
+        paging: Paging,
+    ) -> Result<Vec<SearchResult>, String> {
+        let room = self
+            .find_room(room_id)
+            .ok_or(format!("search_messages: unknown room {}", room_id))?;
+
+        let body = self
+            .get(&with_query(
+                "api/v1/chat.search",
+                &[
+                    ("roomId", room_id),
+                    ("searchText", query),
+                    ("offset", &paging.offset.to_string()),
+                    ("count", &paging.count.to_string()),
+                ],
+            ))
+            .await?;
+        if !body["success"].as_bool().unwrap_or(false) {
+            return Err(format!("chat.search failed: {}", redact::json(&body)));
+        }
+
+        Ok(Message::list_from_json(&body["messages"])?
+            .into_iter()
+            .map(|message| SearchResult {
+                message,
+                room: room.clone(),
+            })
+            .collect())
+    }
+
+    /// Searches the messages of every room we're in, newest first
+    /// Rooms that fail to be searched are skipped
+    pub async fn search_all_messages(
+        &self,
+        query: &str,
+        paging: Paging,
+    ) -> Result<Vec<SearchResult>, String> {
+        // Each room needs to provide a full page, as we don't know yet which results make the cut
+        let per_room_paging = Paging {
+            offset: 0,
+            count: paging.offset + paging.count,
+        };
+
+        let mut results = self
+            .fan_out_over_rooms("search_all_messages", |room_id| async move {
+                self.search_messages(&room_id, query, per_room_paging).await
+            })
+            .await;
+
+        results.sort_by_key(|r| std::cmp::Reverse(r.message.timestamp));
+        Ok(results
+            .into_iter()
+            .skip(paging.offset as usize)
+            .take(paging.count as usize)
+            .collect())
+    }
+
+    /// Pins a message to its room
+    pub async fn pin_message(&self, message_id: &str) -> Result<(), String> {
+        self.message_action("api/v1/chat.pinMessage", message_id)
+            .await
+    }
+
+    pub async fn unpin_message(&self, message_id: &str) -> Result<(), String> {
+        self.message_action("api/v1/chat.unPinMessage", message_id)
+            .await
+    }
+
+    /// Stars a message, starred messages are only visible to us
+    pub async fn star_message(&self, message_id: &str) -> Result<(), String> {
+        self.message_action("api/v1/chat.starMessage", message_id)
+            .await
+    }
+
+    pub async fn unstar_message(&self, message_id: &str) -> Result<(), String> {
+        self.message_action("api/v1/chat.unStarMessage", message_id)
+            .await
+    }
+
+    async fn message_action(&self, endpoint: &str, message_id: &str) -> Result<(), String> {
+        let mut map = HashMap::new();
+        map.insert("messageId", message_id);
+        let body = self.post(endpoint, map).await?;
+        if !body["success"].as_bool().unwrap_or(false) {
+            return Err(format!("{} failed: {}", endpoint, redact::json(&body)));
+        }
+        self.emit(Event::MessageUpdated(String::from(message_id)));
+        Ok(())
+    }
+
+    /// Lists the messages pinned in a room, newest first
+    pub async fn pinned_messages(
+        &self,
+        room_id: &str,
+        paging: Paging,
+    ) -> Result<MessagesPage, String> {
+        self.messages_page("api/v1/chat.getPinnedMessages", room_id, paging)
+            .await
+    }
+
+    /// Lists the messages we starred in a room, newest first
+    pub async fn starred_messages(
+        &self,
+        room_id: &str,
+        paging: Paging,
+    ) -> Result<MessagesPage, String> {
+        self.messages_page("api/v1/chat.getStarredMessages", room_id, paging)
+            .await
+    }
+
+    /// Lists the messages we starred in every room, newest first
+    /// Rooms that fail to be queried are skipped
+    pub async fn all_starred_messages(&self) -> Vec<Message> {
+        let mut messages = self
+            .fan_out_over_rooms("all_starred_messages", |room_id| async move {
+                let mut messages = Vec::new();
+                let mut paging = Paging::default();
+                loop {
+                    let page = self.starred_messages(&room_id, paging).await?;
+                    let num_fetched = page.messages.len() as u64;
+                    messages.extend(page.messages);
+                    paging.offset += num_fetched;
+                    if num_fetched == 0 || paging.offset >= page.total {
+                        return Ok(messages);
+                    }
+                }
+            })
+            .await;
+
+        messages.sort_by_key(|m| std::cmp::Reverse(m.timestamp));
+        messages
+    }
+
+    /// Fetches a page of a room's messages from an endpoint taking roomId, offset and count
+    async fn messages_page(
+        &self,
+        endpoint: &str,
+        room_id: &str,
+        paging: Paging,
+    ) -> Result<MessagesPage, String> {
+        let body = self
+            .get(&with_query(
+                endpoint,
+                &[
+                    ("roomId", room_id),
+                    ("offset", &paging.offset.to_string()),
+                    ("count", &paging.count.to_string()),
+                ],
+            ))
+            .await?;
+        if !body["success"].as_bool().unwrap_or(false) {
+            return Err(format!("{} failed: {}", endpoint, redact::json(&body)));
+        }
+
+        MessagesPage::from_json(&body)
+    }
+
+    /// Returns a room's messages, newest first
+    /// The room needs to be known already, see list_rooms()
+    pub async fn history(&self, room_id: &str, paging: Paging) -> Result<MessagesPage, String> {
+        let endpoint = self.room_endpoint(room_id, "history")?;
+        self.messages_page(&endpoint, room_id, paging).await
+    }
+
+    /// Returns the messages posted in a room after `timestamp`, in seconds, newest first
+    pub async fn history_since(
+        &self,
+        room_id: &str,
+        timestamp: i64,
+    ) -> Result<Vec<Message>, String> {
+        let endpoint = self.room_endpoint(room_id, "history")?;
+        let oldest = timestamp_to_str(timestamp)?;
+
+        let body = self
+            .get(&with_query(
+                &endpoint,
+                &[("roomId", room_id), ("oldest", &oldest), ("count", "100")],
+            ))
+            .await?;
+        if !body["success"].as_bool().unwrap_or(false) {
+            return Err(format!("{} failed: {}", endpoint, redact::json(&body)));
+        }
+
+        Message::list_from_json(&body["messages"])
+    }
+
+    /// Returns the messages within `range` as sent by the server, oldest first
+    async fn history_range_json(
+        &self,
+        room_id: &str,
+        range: ExportRange,
+    ) -> Result<Vec<serde_json::Value>, String> {
+        const PAGE_SIZE: usize = 100;
+        let endpoint = self.room_endpoint(room_id, "history")?;
+        let mut bounds = Vec::new();
+        if let Some(oldest) = range.oldest {
+            bounds.push(("oldest", timestamp_to_str(oldest)?));
+        }
+        if let Some(latest) = range.latest {
+            bounds.push(("latest", timestamp_to_str(latest)?));
+        }
+
+        let mut messages = Vec::new();
+        loop {
+            let offset = messages.len().to_string();
+            let count = PAGE_SIZE.to_string();
+            let mut params = vec![
+                ("roomId", room_id),
+                ("offset", offset.as_str()),
+                ("count", count.as_str()),
+            ];
+            params.extend(bounds.iter().map(|(key, value)| (*key, value.as_str())));
+
+            let body = self.get(&with_query(&endpoint, &params)).await?;
+            if !body["success"].as_bool().unwrap_or(false) {
+                return Err(format!("{} failed: {}", endpoint, redact::json(&body)));
+            }
+
+            let page = body["messages"]
+                .as_array()
+                .ok_or("messages is missing")?
+                .clone();
+            let last_page = page.len() < PAGE_SIZE;
+            messages.extend(page);
+            if last_page {
+                break;
+            }
+        }
+
+        messages.reverse();
+        Ok(messages)
+    }
+
+    /// Copies the attachments of `messages` into `dir`, returning where each one went
+    async fn export_files(&self, messages: &[Message], dir: &Path) -> export::Files {
+        let mut files = export::Files::new();
+        let folder = dir.file_name().unwrap_or_default().to_string_lossy();
+        for message in messages {
+            for attachment in &message.attachments {
+                let name = export::file_name(&format!(
+                    "{}_{}",
+                    message.id,
+                    attachment.path.rsplit('/').next().unwrap_or_default()
+                ));
+                let copied = match self.download(&attachment.path).await {
+                    Ok(cached) => paths::ensure_created(dir)
+                        .and_then(|dir| {
+                            std::fs::copy(cached, dir.join(&name)).map_err(|e| e.to_string())
+                        })
+                        .map(|_| format!("{}/{}", folder, name)),
+                    Err(e) => Err(e),
+                };
+                let link = copied.unwrap_or_else(|e| {
+                    log::warn!("Couldn't export {}: {}", attachment.path, e);
+                    format!("{}/{}", self.url, attachment.path)
+                });
+                files.insert(attachment.path.clone(), link);
+            }
+        }
+        files
+    }
+
+    /// Writes a room's history into `dir`, as `<room>.<extension>`, with attachments in `<room>_files`
+    /// Returns the path of the written file. The room needs to be known already, see list_rooms().
+    pub async fn export_room(
+        &self,
+        room_id: &str,
+        range: ExportRange,
+        format: ExportFormat,
+        dir: &Path,
+    ) -> Result<PathBuf, String> {
+        let room = self
+            .find_room(room_id)
+            .ok_or(format!("Unknown room {}", room_id))?;
+        let raw = self.history_range_json(room_id, range).await?;
+        let messages: Vec<Message> = raw
+            .iter()
+            .map(Message::from_json)
+            .collect::<Result<_, _>>()?;
+        log::info!(
+            "Exporting {} messages from {}",
+            messages.len(),
+            room.display_name()
+        );
+
+        let name = export::file_name(&room.display_name());
+        let dir = paths::ensure_created(dir)?;
+        let files = self
+            .export_files(&messages, &dir.join(format!("{}_files", name)))
+            .await;
+
+        let contents = match format {
+            ExportFormat::Json => {
+                serde_json::to_string_pretty(&export::to_json(&room, &raw, &files))
+                    .map_err(|e| e.to_string())?
+            }
+            ExportFormat::Markdown => export::to_markdown(&room, &messages, &files),
+            ExportFormat::Html => export::to_html(&room, &messages, &files),
+        };
+        let path = dir.join(format!("{}.{}", name, format.extension()));
+        std::fs::write(&path, contents)
+            .map_err(|e| format!("Couldn't write {}: {}", path.display(), e))?;
+        Ok(path)
+    }
+
+    /// Posts a message to a room, returning it as stored by the server
+    pub async fn send_message(&self, room_id: &str, text: &str) -> Result<Message, String> {
+        self.post_message(serde_json::json!({ "rid": room_id, "msg": text }))
+            .await
+    }
+
+    /// Replies in the thread started by `thread_id`, starting it if needed
+    pub async fn send_thread_reply(
+        &self,
+        room_id: &str,
+        thread_id: &str,
+        text: &str,
+    ) -> Result<Message, String> {
+        self.post_message(serde_json::json!({ "rid": room_id, "msg": text, "tmid": thread_id }))
+            .await
+    }
+
+    async fn post_message(&self, message: serde_json::Value) -> Result<Message, String> {
+        let body = self
+            .post_json(
+                "api/v1/chat.sendMessage",
+                serde_json::json!({ "message": message }),
+            )
+            .await?;
+        if !body["success"].as_bool().unwrap_or(false) {
+            return Err(format!("chat.sendMessage failed: {}", redact::json(&body)));
+        }
+
+        Message::from_json(&body["message"])
+    }
+
+    /// Queues a message, then tries to send it along with anything queued before it
+    /// Doesn't fail if offline, the message stays queued for flush_outbox(). Follow it via
+    /// Event::OutboxChanged, or the returned copy which has the state after the first attempt.
+    pub async fn queue_message(
+        &self,
+        room_id: &str,
+        thread_id: Option<&str>,
+        text: &str,
+    ) -> OutboxMessage {
+        let message = OutboxMessage {
+            id: outbox::generate_id(),
+            room_id: String::from(room_id),
+            thread_id: thread_id.map(String::from),
+            text: String::from(text),
+            queued_at: chrono::Utc::now().timestamp(),
+            state: OutboxState::Queued,
+        };
+        self.outbox.lock().unwrap().push(message.clone());
+        self.emit(Event::OutboxChanged(message.clone()));
+
+        self.send_queued(room_id).await;
+        let state = self
+            .outbox()
+            .into_iter()
+            .find(|m| m.id == message.id)
+            .map(|m| m.state)
+            .unwrap_or(OutboxState::Sent);
+        OutboxMessage { state, ..message }
+    }
+
+    /// Returns the messages not yet sent, oldest first
+    pub fn outbox(&self) -> Vec<OutboxMessage> {
+        self.outbox.lock().unwrap().messages()
+    }
+
+    /// Sends what's queued in every room, for when the connection is back
+    pub async fn flush_outbox(&self) {
+        let rooms = self.outbox.lock().unwrap().rooms();
+        for room_id in rooms {
+            self.send_queued(&room_id).await;
+        }
+    }
+
+    /// Queues a failed message again, and tries sending its room's queue
+    pub async fn retry_outbox_message(&self, id: &str) -> Result<(), String> {
+        let room_id = self
+            .set_outbox_state(id, OutboxState::Queued)
+            .ok_or(format!("{} isn't in the outbox", id))?
+            .room_id;
+        self.send_queued(&room_id).await;
+        Ok(())
+    }
+
+    /// Drops a message from the outbox, letting the ones after it through
+    pub fn discard_outbox_message(&self, id: &str) -> Result<(), String> {
+        self.outbox
+            .lock()
+            .unwrap()
+            .remove(id)
+            .ok_or(format!("{} isn't in the outbox", id))?;
+        self.emit(Event::OutboxDiscarded(String::from(id)));
+        Ok(())
+    }
+
+    /// Parks the outbox of whoever was logged in, and brings back `user_id`'s
+    fn switch_outbox(&self, user_id: &str) {
+        let (parked, restored) = self.outbox.lock().unwrap().set_user(user_id);
+        for message in parked {
+            self.emit(Event::OutboxDiscarded(message.id));
+        }
+        for message in restored {
+            self.emit(Event::OutboxChanged(message));
+        }
+    }
+
+    /// Returns None if the message isn't in the outbox, which for Sent means it was already handled
+    fn set_outbox_state(&self, id: &str, state: OutboxState) -> Option<OutboxMessage> {
+        let message = self.outbox.lock().unwrap().set_state(id, state)?;
+        self.emit(Event::OutboxChanged(message.clone()));
+        Some(message)
+    }
+
+    /// Sends a room's queued messages in order
+    /// Stops at the first one that can't be sent, so none overtakes another
+    async fn send_queued(&self, room_id: &str) {
+        let _sending = self.outbox_sending.lock().await;
+        loop {
+            let next = self.outbox.lock().unwrap().next_queued(room_id);
+            let Some(message) = next else {
+                break;
+            };
+            self.set_outbox_state(&message.id, OutboxState::Sending);
+
+            let result = self
+                .request_with_status(
+                    reqwest::Method::POST,
+                    "api/v1/chat.sendMessage",
+                    &Payload::Json(&serde_json::json!({ "message": message.to_request() })),
+                )
+                .await;
+            let state = match result {
+                Ok((_, body)) if body["success"].as_bool().unwrap_or(false) => OutboxState::Sent,
+                // Sent before, but we didn't get to hear back
+                Ok((_, body))
+                    if body["errorType"].as_str() == Some("error-message-already-exists") =>
+                {
+                    OutboxState::Sent
+                }
+                // Rate limited, logged out or the server is having trouble: worth another try
+                Ok((status, body))
+                    if status == reqwest::StatusCode::TOO_MANY_REQUESTS
+                        || status.is_server_error()
+                        || is_unauthorized(status, &body) =>
+                {
+                    log::info!(
+                        "Couldn't send {} ({}), keeping it queued",
+                        message.id,
+                        status
+                    );
+                    OutboxState::Queued
+                }
+                Ok((_, body)) => OutboxState::Failed(String::from(
+                    body["error"].as_str().unwrap_or("chat.sendMessage failed"),
+                )),
+                Err(e) => {
+                    log::info!("Couldn't send {}, keeping it queued: {}", message.id, e);
+                    OutboxState::Queued
+                }
+            };
+
+            let sent = state == OutboxState::Sent;
+            // Already Sent if the server's echo got here first
+            self.set_outbox_state(&message.id, state);
+            if !sent {
+                break;
+            }
+        }
+    }
+
+    /// Adds or removes our reaction to a message
+    /// `emoji` is a shortcode, with or without the colons
+    pub async fn react(&self, message_id: &str, emoji: &str, add: bool) -> Result<(), String> {
+        let emoji = format!(":{}:", emoji.trim_matches(':'));
+        let body = self
+            .post_json(
+                "api/v1/chat.react",
+                serde_json::json!({ "messageId": message_id, "emoji": emoji, "shouldReact": add }),
+            )
+            .await?;
+        if !body["success"].as_bool().unwrap_or(false) {
+            return Err(format!("chat.react failed: {}", redact::json(&body)));
+        }
+
+        self.emit(Event::ReactionChanged {
+            message_id: String::from(message_id),
+            emoji,
+            username: self.get_username(),
+            added: add,
+        });
+        Ok(())
+    }
+
+    /// Uploads a file to a room, with an optional message to go with it
+    /// Not retried, as the server can't tell duplicates apart
+    pub async fn upload(
+        &self,
+        room_id: &str,
+        path: &std::path::Path,
+        description: Option<&str>,
+    ) -> Result<Message, String> {
+        let file_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or(format!("{} is not a file", path.display()))?
+            .to_string();
+        let bytes = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        let payload = Payload::File {
+            name: &file_name,
+            bytes: &bytes,
+            description,
+        };
+        let (_, body) = self
+            .request_with_status(
+                reqwest::Method::POST,
+                &format!("api/v1/rooms.upload/{}", room_id),
+                &payload,
+            )
+            .await?;
+        if !body["success"].as_bool().unwrap_or(false) {
+            return Err(format!("rooms.upload failed: {}", redact::json(&body)));
+        }
+
+        Message::from_json(&body["message"])
+    }
+
+    /// Browses the server's directory of channels, users or teams
+    pub async fn search_directory(
+        &self,
+        kind: DirectoryKind,
+        text: &str,
+        sort: Option<Sort>,
+        paging: Paging,
+    ) -> Result<DirectoryPage, String> {
+        let sort = sort.map(|s| s.to_json());
+        let offset = paging.offset.to_string();
+        let count = paging.count.to_string();
+        let mut params = vec![
+            ("type", kind.as_str()),
+            ("text", text),
+            ("workspace", "local"),
+            ("offset", &offset),
+            ("count", &count),
+        ];
+        if let Some(sort) = &sort {
+            params.push(("sort", sort));
+        }
+
+        let body = self.get(&with_query("api/v1/directory", &params)).await?;
+        if !body["success"].as_bool().unwrap_or(false) {
+            return Err(format!("directory failed: {}", redact::json(&body)));
+        }
+
+        DirectoryPage::from_json(kind, &body)
+    }
+
+    /// Joins a public channel
+    pub async fn join_channel(&self, room_id: &str) -> Result<(), String> {
+        let mut map = HashMap::new();
+        map.insert("roomId", room_id);
+        let body = self.post("api/v1/channels.join", map).await?;
+        if !body["success"].as_bool().unwrap_or(false) {
+            return Err(format!("channels.join failed: {}", redact::json(&body)));
+        }
+
+        self.add_room(Room::Channel(Channel::from_json(&body["channel"])?));
+        Ok(())
+    }
+
+    /// Leaves a channel or private group
+    /// Direct rooms can't be left, there's no im.leave.
+    pub async fn leave_room(&self, room_id: &str) -> Result<(), String> {
+        if self.room_endpoint_family(room_id)? == EndpointFamily::Im {
+            return Err(format!("{} is a direct room, which can't be left", room_id));
+        }
+        let mut map = HashMap::new();
+        map.insert("roomId", room_id);
+        let endpoint = self.room_endpoint(room_id, "leave")?;
+        let body = self.post(&endpoint, map).await?;
+        if !body["success"].as_bool().unwrap_or(false) {
+            return Err(format!("{} failed: {}", endpoint, redact::json(&body)));
+        }
+
+        self.remove_room(room_id);
+        Ok(())
+    }
+
+    /// Opens a direct conversation with someone, creating it if needed
+    /// Returns the room ID
+    pub async fn open_direct(&self, username: &str) -> Result<String, String> {
+        let mut map = HashMap::new();
+        map.insert("username", username);
+        let body = self.post("api/v1/im.create", map).await?;
+        if !body["success"].as_bool().unwrap_or(false) {
+            return Err(format!("im.create failed: {}", redact::json(&body)));
+        }
+
+        let room = DirectRoom::from_json(&body["room"])?;
+        let room_id = room.id.clone();
+        self.add_room(Room::Direct(room));
+        Ok(room_id)
+    }
+
+    /// Lists who is in a room
+    /// If `statuses` isn't empty, only members with one of those statuses are returned
+    pub async fn room_members(
+        &self,
+        room_id: &str,
+        statuses: &[UserStatus],
+        paging: Paging,
+    ) -> Result<MembersPage, String> {
+        let endpoint = self.room_endpoint(room_id, "members")?;
+        let offset = paging.offset.to_string();
+        let count = paging.count.to_string();
+        let mut params = vec![("roomId", room_id), ("offset", &offset), ("count", &count)];
+        for status in statuses {
+            params.push(("status[]", status.as_str()));
+        }
+
+        let body = self.get(&with_query(&endpoint, &params)).await?;
+        if !body["success"].as_bool().unwrap_or(false) {
+            return Err(format!("{} failed: {}", endpoint, redact::json(&body)));
+        }
+
+        MembersPage::from_json(&body)
+    }
+
+    /// Lists the owners, moderators and leaders of a channel or group
+    pub async fn room_roles(&self, room_id: &str) -> Result<RoomRoles, String> {
+        match self.room_endpoint_family(room_id)? {
+            EndpointFamily::Channels | EndpointFamily::Groups => {}
+            _ => return Err(format!("room_roles: {} has no roles", room_id)),
+        }
+
+        let endpoint = self.room_endpoint(room_id, "roles")?;
+
+        let body = self
+            .get(&with_query(&endpoint, &[("roomId", room_id)]))
+            .await?;
+        if !body["success"].as_bool().unwrap_or(false) {
+            return Err(format!("{} failed: {}", endpoint, redact::json(&body)));
+        }
+
+        RoomRoles::from_json(&body)
+    }
+
+    /// Whether we're an owner or moderator of a channel or group
+    pub async fn can_moderate(&self, room_id: &str) -> Result<bool, String> {
+        let roles = self.room_roles(room_id).await?;
+        Ok(roles.can_moderate(&self.get_user_id()))
+    }
+
+    /// Kicks, mutes, changes the topic and so on
+    /// Fails without asking the server if the room roles say we're not allowed to.
+    /// Server-wide admins without a role in the room are refused too.
+    pub async fn moderate(&self, room_id: &str, action: ModerationAction) -> Result<(), String> {
+        let family = self.room_endpoint_family(room_id)?;
+        if family != EndpointFamily::Channels && family != EndpointFamily::Groups {
+            return Err(format!("moderate: {} can't be moderated", room_id));
+        }
+
+        let roles = self.room_roles(room_id).await?;
+        let user_id = self.get_user_id();
+        let allowed = if action.requires_owner() {
+            roles.is_owner(&user_id)
+        } else {
+            roles.can_moderate(&user_id)
+        };
+        if !allowed {
+            return Err(format!(
+                "moderate: not allowed to {:?} in {}",
+                action, room_id
+            ));
+        }
+
+        let legacy_mute = match &action {
+            ModerationAction::Mute(muted_user_id) | ModerationAction::Unmute(muted_user_id) => {
+                let version = self.server_info().await?.version_numbers();
+                if version.is_some_and(|v| v < moderation::ROOMS_MUTE_VERSION) {
+                    let username = self.username_of(muted_user_id).await?;
+                    action.legacy_mute_request(room_id, &username)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        };
+
+        if let Some((endpoint, payload)) = legacy_mute {
+            let body = self.post_json(&endpoint, payload).await?;
+            moderation::method_call_result(&body)
+                .map_err(|e| format!("{} failed: {}", endpoint, e))?;
+        } else {
+            let endpoint = action.endpoint(family);
+            let body = self.post_json(&endpoint, action.payload(room_id)).await?;
+            if !body["success"].as_bool().unwrap_or(false) {
+                return Err(format!("{} failed: {}", endpoint, redact::json(&body)));
+            }
+        }
+
+        if let ModerationAction::Kick(kicked_user_id) = &action {
+            if *kicked_user_id == user_id {
+                self.remove_room(room_id);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Looks up someone's username by user ID
+    async fn username_of(&self, user_id: &str) -> Result<String, String> {
+        let body = self
+            .get(&with_query("api/v1/users.info", &[("userId", user_id)]))
+            .await?;
+        body["user"]["username"]
+            .as_str()
+            .map(String::from)
+            .ok_or(format!("users.info failed: {}", redact::json(&body)))
+    }
+
+    /// Creates a public channel, or a private group
+    /// `members` are usernames, we're added automatically
+    pub async fn create_channel(
+        &self,
+        name: &str,
+        members: &[&str],
+        read_only: bool,
+        private: bool,
+    ) -> Result<Channel, CreateRoomError> {
+        let (endpoint, key) = if private {
+            ("api/v1/groups.create", "group")
+        } else {
+            ("api/v1/channels.create", "channel")
+        };
+
+        let body = self
+            .post_json(
+                endpoint,
+                serde_json::json!({
+                    "name": name,
+                    "members": members,
+                    "readOnly": read_only,
+                }),
+            )
+            .await?;
+        if !body["success"].as_bool().unwrap_or(false) {
+            return Err(CreateRoomError::from_response(name, &body));
+        }
+
+        let channel = Channel::from_json(&body[key])?;
+        self.add_room(Room::Channel(channel.clone()));
+        Ok(channel)
+    }
+
+    /// Creates a discussion in `parent_room_id`
+    /// `first_message` is posted in the new discussion, `users` are usernames to invite
+    pub async fn create_discussion(
+        &self,
+        parent_room_id: &str,
+        name: &str,
+        first_message: Option<&str>,
+        users: &[&str],
+    ) -> Result<Channel, CreateRoomError> {
+        let mut data = serde_json::json!({
+            "prid": parent_room_id,
+            "t_name": name,
+            "users": users,
+        });
+        if let Some(first_message) = first_message {
+            data["reply"] = serde_json::json!(first_message);
+        }
+
+        let body = self
+            .post_json("api/v1/rooms.createDiscussion", data)
+            .await?;
+        if !body["success"].as_bool().unwrap_or(false) {
+            return Err(CreateRoomError::from_response(name, &body));
+        }
+
+        let discussion = Channel::from_json(&body["discussion"])?;
+        self.add_room(Room::Channel(discussion.clone()));
+        Ok(discussion)
+    }
+
+    /// Fetches our subscriptions, with unread counts and notification preferences
+    /// They're merged into the rooms returned by get_channel_rooms() and friends
+    pub async fn list_subscriptions(&self) -> Result<(), String> {
+        if !self.is_logged_in() {
+            return Err(String::from("not logged in"));
+        }
+        let body = self.get("api/v1/subscriptions.get").await?;
+        if !body["success"].as_bool().unwrap_or(false) {
+            return Err(format!("subscriptions.get failed: {}", redact::json(&body)));
+        }
+
+        let subscriptions = body["update"]
+            .as_array()
+            .ok_or("subscriptions.get: update is missing")?
+            .iter()
+            .map(|s| Subscription::from_json(s).map(|s| (s.room_id.clone(), s)))
+            .collect::<Result<HashMap<_, _>, String>>()?;
+
+        {
+            let mut data = self.exclusive_data.lock().unwrap();
+            data.subscriptions = subscriptions;
+            data.apply_subscriptions();
+            // The server's counts are up to date now, only highlights are ours to count
+            for counters in data.mention_counters.values_mut() {
+                *counters = MentionCounters {
+                    highlight: counters.highlight,
+                    ..Default::default()
+                };
+            }
+        }
+        self.emit(Event::RoomsReset);
+        Ok(())
+    }
+
+    /// Fetches everything needed after login, all at the same time
+    /// Progress is reported as Event::SyncProgress. The first fatal failure cancels the
+    /// steps still running, which then never report finishing.
+    pub async fn initial_sync(&self) -> Result<(), String> {
+        futures::try_join!(
+            self.sync_step(SyncStep::ServerInfo, async {
+                self.server_info().await.map(|_| ())
+            }),
+            self.sync_step(SyncStep::JoinedChannels, self.list_joined_channels()),
+            self.sync_step(SyncStep::Rooms, self.list_rooms()),
+            self.sync_step(SyncStep::Subscriptions, self.list_subscriptions()),
+            self.sync_step(SyncStep::Profile, self.load_profile()),
+            self.sync_step(SyncStep::CustomEmojis, async {
+                self.custom_emojis().await.map(|_| ())
+            }),
+        )?;
+        Ok(())
+    }
+
+    /// Runs one step of initial_sync(), reporting its progress
+    /// Failing steps that aren't fatal are only reported
+    async fn sync_step<F>(&self, step: SyncStep, f: F) -> Result<(), String>
+    where
+        F: std::future::Future<Output = Result<(), String>>,
+    {
+        self.emit(Event::SyncProgress(step, StepState::Running));
+        match f.await {
+            Ok(()) => {
+                self.emit(Event::SyncProgress(step, StepState::Done));
+                Ok(())
+            }
+            Err(e) => {
+                self.emit(Event::SyncProgress(step, StepState::Failed(e.clone())));
+                if step.is_fatal() {
+                    Err(format!("{:?} failed: {}", step, e))
+                } else {
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    /// Marks a room as favorite, favorites are listed first
+    pub async fn set_favorite(&self, room_id: &str, favorite: bool) -> Result<(), String> {
+        let body = self
+            .post_json(
+                "api/v1/rooms.favorite",
+                serde_json::json!({ "roomId": room_id, "favorite": favorite }),
+            )
+            .await?;
+        if !body["success"].as_bool().unwrap_or(false) {
+            return Err(format!("rooms.favorite failed: {}", redact::json(&body)));
+        }
+
+        self.update_subscription(room_id, |s| s.favorite = favorite);
+        Ok(())
+    }
+
+    /// Hides a room from the sidebar, without leaving it
+    pub async fn hide_room(&self, room_id: &str) -> Result<(), String> {
+        self.set_room_open(room_id, false).await
+    }
+
+    /// Shows a room that was hidden
+    pub async fn open_room(&self, room_id: &str) -> Result<(), String> {
+        self.set_room_open(room_id, true).await
+    }
+
+    async fn set_room_open(&self, room_id: &str, open: bool) -> Result<(), String> {
+        let endpoint = if open {
+            "api/v1/rooms.open"
+        } else {
+            "api/v1/rooms.hide"
+        };
+        let mut map = HashMap::new();
+        map.insert("roomId", room_id);
+        let body = self.post(endpoint, map).await?;
+        if !body["success"].as_bool().unwrap_or(false) {
+            return Err(format!("{} failed: {}", endpoint, redact::json(&body)));
+        }
+
+        self.update_subscription(room_id, |s| s.open = open);
+        Ok(())
+    }
+
+    /// Changes how we're notified about a room
+    pub async fn save_notification_preferences(
+        &self,
+        room_id: &str,
+        preferences: &NotificationPreferences,
+    ) -> Result<(), String> {
+        let body = self
+            .post_json(
+                "api/v1/rooms.saveNotification",
+                serde_json::json!({ "roomId": room_id, "notifications": preferences.to_json() }),
+            )
+            .await?;
+        if !body["success"].as_bool().unwrap_or(false) {
+            return Err(format!(
+                "rooms.saveNotification failed: {}",
+                redact::json(&body)
+            ));
+        }
+
+        self.update_subscription(room_id, |s| preferences.apply(s));
+        Ok(())
+    }
+
+    pub async fn list_joined_channels(&self) -> Result<(), String> {
+        if !self.is_logged_in() {
+            return Err(String::from("not logged in"));
+        }
+        let body = self.get("api/v1/channels.list.joined").await?;
+        // serde_json::to_writer_pretty(std::io::stdout(), &body).unwrap();
+        let channels = body["channels"]
+            .as_array()
+            .ok_or("channels.list.joined: channels is missing")?;
+        let mut joined_channels = Vec::new();
+        for c in channels {
+            joined_channels.push(Channel::from_json(c)?);
+        }
+        self.set_joined_channels(joined_channels);
+        Ok(())
+    }
+
+    pub async fn list_rooms(&self) -> Result<(), String> {
+        if !self.is_logged_in() {
+            return Err(String::from("not logged in"));
+        }
+        let body = self.get("api/v1/rooms.get").await?;
+        // serde_json::to_writer_pretty(std::io::stdout(), &body).unwrap();
+        let success = body["success"].as_bool().unwrap_or(false);
+        if !success {
+            return Err(format!(
+                "list_rooms: Failed to list rooms! {}",
+                redact::json(&body)
+            ));
+        }
+
+        let mut direct_rooms = Vec::new();
+        let mut channel_rooms = Vec::new();
+
+        let rooms = body["update"]
+            .as_array()
+            .ok_or("rooms.get: update is missing")?;
+        for r in rooms {
+            match Room::from_json(r) {
+                Ok(Room::Direct(room)) => direct_rooms.push(room),
+                Ok(Room::Channel(room)) => channel_rooms.push(room),
+                Err(e) => log::warn!("list_rooms: {}", e),
+            }
+        }
+
+        self.set_direct_rooms(direct_rooms);
+        self.set_channel_rooms(channel_rooms);
+        Ok(())
+    }
+}
+
+/// What a request carries
+enum Payload<'a> {
+    Empty,
+    Json(&'a serde_json::Value),
+    /// A file, sent as a multipart form
+    File {
+        name: &'a str,
+        bytes: &'a [u8],
+        description: Option<&'a str>,
+    },
+}
+
+impl Payload<'_> {
+    /// Built again for every attempt, as forms can't be cloned
+    fn attach(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match self {
+            Payload::Empty => request,
+            Payload::Json(data) => request.json(data),
+            Payload::File {
+                name,
+                bytes,
+                description,
+            } => {
+                let mut form = reqwest::multipart::Form::new().part(
+                    "file",
+                    reqwest::multipart::Part::bytes(bytes.to_vec()).file_name(name.to_string()),
+                );
+                if let Some(description) = description {
+                    form = form.text("description", description.to_string());
+                }
+                request.multipart(form)
+            }
+        }
+    }
+
+    /// For the log, with secrets redacted and files left out
+    fn describe(&self) -> String {
+        match self {
+            Payload::Empty => String::new(),
+            Payload::Json(data) => redact::json(data).to_string(),
+            Payload::File { name, bytes, .. } => format!("{} ({} bytes)", name, bytes.len()),
+        }
+    }
+}
+
+/// How a request proves who we are
+#[derive(Clone, Copy)]
+enum Credentials {
+    None,
+    /// X-User-Id and X-Auth-Token, for the REST API
+    Headers,
+    /// rc_uid and rc_token, for file routes
+    Cookie,
+}
+
+/// Whether the server rejected our credentials, or wanted some
+fn is_unauthorized(status: reqwest::StatusCode, json: &serde_json::Value) -> bool {
+    status == reqwest::StatusCode::UNAUTHORIZED
+        || json["message"].as_str() == Some("You must be logged in to do this.")
+}
+
+/// Wraps each room for the shared room lists
+fn shared_rooms<T>(rooms: Vec<T>, f: fn(T) -> Room) -> Vec<Arc<Room>> {
+    rooms.into_iter().map(|room| Arc::new(f(room))).collect()
+}
+
+/// Appends url-encoded query parameters to an endpoint
+fn with_query(endpoint: &str, params: &[(&str, &str)]) -> String {
+    let query = url::form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(params)
+        .finish();
+    format!("{}?{}", endpoint, query)
+}
+
+/// The inverse of str_to_timestamp(), for query parameters
+fn timestamp_to_str(timestamp: i64) -> Result<String, String> {
+    chrono::Utc
+        .timestamp_opt(timestamp, 0)
+        .single()
+        .map(|t| t.to_rfc3339_opts(chrono::SecondsFormat::Millis, true))
+        .ok_or(format!("invalid timestamp {}", timestamp))
+}
+
+/// When the room's last message was posted, from either API
+fn last_message_timestamp(r: &serde_json::Value) -> Result<Option<i64>, String> {
+    message::timestamp(&r["lm"]).map(|ts| (ts >= 0).then_some(ts))
+}
+
+/// Parses the server's dates, for example 2022-05-17T14:55:23.276Z, into seconds
+fn str_to_timestamp(s: &str) -> Result<i64, String> {
+    chrono::DateTime::parse_from_rfc3339(s)
+        .map(|t| t.timestamp())
+        .map_err(|e| format!("invalid date {:?}: {}", s, e))
+}
+
+#[cfg(test)]
+mod tests {
     pub const RC_SLINT_TEST_USER: &str = env!("RC_SLINT_TEST_USER");
     pub const RC_SLINT_TEST_PWD: &str = env!("RC_SLINT_TEST_PWD");
     pub const RC_SLINT_TEST_URL: &str = env!("RC_SLINT_TEST_URL");
 
     use super::*;
 
+    /// A client keeping its files in a fresh temporary directory
+    fn test_client(url: &str) -> (tempfile::TempDir, RocketChat) {
+        let dir = tempfile::tempdir().unwrap();
+        let rc = RocketChat::new(url, Paths::in_dir(dir.path()));
+        (dir, rc)
+    }
+
+    fn test_server() -> (tempfile::TempDir, RocketChat) {
+        test_client(&format!("https://{}", RC_SLINT_TEST_URL))
+    }
+
     #[tokio::test]
     async fn test_login() {
-        let rc = RocketChat::new(std::format!("https://{}", RC_SLINT_TEST_URL).as_str(), "");
+        let (_dir, rc) = test_server();
+        rc.login(RC_SLINT_TEST_USER, RC_SLINT_TEST_PWD)
+            .await
+            .expect("failed");
+        assert!(rc.is_logged_in());
+    }
+
+    #[tokio::test]
+    async fn test_server_info() {
+        let (_dir, rc) = test_server();
+        let server_info = rc.server_info().await.expect("failed");
+        assert!(server_info.version_numbers().is_some());
+        assert_eq!(rc.cached_server_info(), Some(server_info));
+    }
+
+    #[tokio::test]
+    async fn test_search_messages() {
+        let (_dir, rc) = test_server();
+        rc.login(RC_SLINT_TEST_USER, RC_SLINT_TEST_PWD)
+            .await
+            .expect("failed");
+        rc.list_rooms().await.expect("failed");
+
+        let results = rc
+            .search_all_messages("a", Paging::default())
+            .await
+            .expect("failed");
+        assert!(results
+            .windows(2)
+            .all(|w| w[0].message.timestamp >= w[1].message.timestamp));
+    }
+
+    #[tokio::test]
+    async fn test_directory() {
+        let (_dir, rc) = test_server();
+        rc.login(RC_SLINT_TEST_USER, RC_SLINT_TEST_PWD)
+            .await
+            .expect("failed");
+
+        let page = rc
+            .search_directory(
+                DirectoryKind::Users,
+                RC_SLINT_TEST_USER,
+                Some(Sort::ascending("username")),
+                Paging::default(),
+            )
+            .await
+            .expect("failed");
+        assert!(page.entries.iter().any(|entry| matches!(
+            entry,
+            DirectoryEntry::User(user) if user.username == RC_SLINT_TEST_USER
+        )));
+    }
+
+    #[test]
+    fn test_create_room_error() {
+        let body = serde_json::json!({
+            "success": false,
+            "error": "A channel with name 'general' exists [error-duplicate-channel-name]",
+            "errorType": "error-duplicate-channel-name"
+        });
+        assert_eq!(
+            CreateRoomError::from_response("general", &body),
+            CreateRoomError::DuplicateName(String::from("general"))
+        );
+
+        let body = serde_json::json!({ "success": false, "errorType": "error-invalid-room-name" });
+        assert_eq!(
+            CreateRoomError::from_response("a b", &body),
+            CreateRoomError::InvalidName(String::from("a b"))
+        );
+
+        let body = serde_json::json!({ "success": false, "errorType": "error-not-allowed" });
+        assert!(matches!(
+            CreateRoomError::from_response("x", &body),
+            CreateRoomError::Other(_)
+        ));
+    }
+
+    #[test]
+    fn test_outbox_offline() {
+        // Nothing listens on port 1, so sending fails like it would offline
+        let (dir, rc) = test_client("http://127.0.0.1:1");
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let queued = runtime.block_on(rc.queue_message("GENERAL", None, "hello"));
+        assert_eq!(queued.state, OutboxState::Queued);
+        assert_eq!(queued.id.len(), 17);
+        runtime.block_on(rc.queue_message("GENERAL", None, "again"));
+
+        // Survives restarts, in order
+        let rc = RocketChat::new("http://127.0.0.1:1", Paths::in_dir(dir.path()));
+        let outbox = rc.outbox();
+        assert_eq!(outbox.len(), 2);
+        assert_eq!(outbox[0].id, queued.id);
+
+        // The server's echo marks it as sent
+        let mut events = rc.subscribe();
+        let echo = Message::from_json(&serde_json::json!({
+            "_id": queued.id, "rid": "GENERAL", "msg": "hello"
+        }))
+        .unwrap();
+        rc.note_message(&echo);
+        assert!(matches!(
+            events.try_recv(),
+            Ok(Event::OutboxChanged(m)) if m.id == queued.id && m.state == OutboxState::Sent
+        ));
+        assert_eq!(rc.outbox().len(), 1);
+
+        rc.discard_outbox_message(&outbox[1].id).unwrap();
+        assert!(rc.outbox().is_empty());
+        assert!(rc.discard_outbox_message(&outbox[1].id).is_err());
+    }
+
+    #[test]
+    fn test_events() {
+        let (_dir, rc) = test_client("https://localhost");
+        let mut events = rc.subscribe();
+
+        rc.set_joined_channels(Vec::new());
+        assert!(matches!(events.try_recv(), Ok(Event::RoomsReset)));
+
+        rc.remove_room("GENERAL");
+        assert!(matches!(events.try_recv(), Ok(Event::RoomRemoved(id)) if id == "GENERAL"));
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_send_sync() {
+        fn assert_send_sync<T: Send + Sync>(_: &T) {}
+        fn assert_send<T: Send>(_: &T) {}
+
+        let (_dir, rc) = test_client("https://localhost");
+        let rc = Arc::new(rc);
+        assert_send_sync(&rc);
+
+        // Calls have to be spawnable on tokio's worker threads
+        assert_send(&rc.login("user", "password"));
+        assert_send(&rc.list_rooms());
+        assert_send(&rc.list_subscriptions());
+        assert_send(&rc.search_all_messages("text", Paging::default()));
+        assert_send(&rc.all_starred_messages());
+        assert_send(&rc.moderate("GENERAL", ModerationAction::Archive));
+        assert_send(&rc.custom_emojis());
+        assert_send(&rc.logout());
+        assert_send(&rc.initial_sync());
+    }
+
+    #[test]
+    fn test_str_to_timestamp() {
+        assert_eq!(str_to_timestamp("2022-05-17T14:55:23.276Z"), Ok(1652799323));
+        assert!(str_to_timestamp("2022-05-17").is_err());
+        assert!(str_to_timestamp("").is_err());
+    }
+
+    #[test]
+    fn test_livechat_endpoints() {
+        let (_dir, rc) = test_client("http://127.0.0.1:1");
+        rc.set_channel_rooms(vec![Channel {
+            room_type: RoomType::Livechat,
+            ..Channel::for_test("LIVE", "visitor")
+        }]);
+
+        // Refused before reaching the server, there's no livechat.history
+        assert_eq!(
+            rc.room_endpoint("LIVE", "history"),
+            Err(String::from("LIVE isn't a channel, group or direct room"))
+        );
+        assert!(rc.room_endpoint("NOPE", "history").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_leave_direct_room() {
+        let (_dir, rc) = test_client("http://127.0.0.1:1");
+        rc.set_direct_rooms(vec![DirectRoom {
+            id: String::from("u1u2"),
+            room_type: RoomType::Direct,
+            num_msgs: 0,
+            usernames: vec![String::from("alice"), String::from("bob")],
+            last_message_timestamp: None,
+            subscription: None,
+        }]);
+
+        // Refused before reaching the server
+        assert_eq!(
+            rc.leave_room("u1u2").await,
+            Err(String::from("u1u2 is a direct room, which can't be left"))
+        );
+        assert!(rc.find_room("u1u2").is_some());
+    }
+
+    #[test]
+    fn test_room_snapshots() {
+        let (_dir, rc) = test_client("https://localhost");
+        rc.set_joined_channels(vec![Channel::for_test("GENERAL", "general")]);
+
+        let snapshot = rc.get_joined_channels();
+        let general = rc.find_room("GENERAL").unwrap();
+        assert_eq!(general.display_name(), "general");
+
+        // Other changes share the room instead of copying it
+        rc.set_direct_rooms(Vec::new());
+        assert!(Arc::ptr_eq(&general, &snapshot[0]));
+        assert!(Arc::ptr_eq(&general, &rc.find_room("GENERAL").unwrap()));
+
+        rc.remove_room("GENERAL");
+        assert!(rc.find_room("GENERAL").is_none());
+        assert!(rc.get_joined_channels().is_empty());
+        assert_eq!(snapshot.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_logout() {
+        let (_dir, rc) = test_server();
         rc.login(RC_SLINT_TEST_USER, RC_SLINT_TEST_PWD)
             .await
             .expect("failed");
-        assert_eq!(rc.is_logged_in(), true);
+        rc.logout().await.expect("failed");
+        assert!(!rc.is_logged_in());
+        assert!(rc.get_auth_token().is_empty());
+        assert!(rc.saved_auth_token().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_resume_session() {
+        let mut server = mockito::Server::new_async().await;
+        let rejected = server
+            .mock("GET", "/api/v1/me")
+            .match_header("X-Auth-Token", "old")
+            .with_status(401)
+            .with_body(r#"{"status": "error", "message": "You must be logged in to do this."}"#)
+            .expect(2)
+            .create_async()
+            .await;
+        let resume = server
+            .mock("POST", "/api/v1/login")
+            .match_header("X-Auth-Token", mockito::Matcher::Missing)
+            .match_body(mockito::Matcher::Json(
+                serde_json::json!({ "resume": "old" }),
+            ))
+            .with_body(r#"{"status": "success", "data": {"userId": "u1", "authToken": "new"}}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        let accepted = server
+            .mock("GET", "/api/v1/me")
+            .match_header("X-Auth-Token", "new")
+            .with_body(r#"{"success": true, "username": "alice"}"#)
+            .expect(2)
+            .create_async()
+            .await;
+
+        let (_dir, rc) = test_client(&server.url());
+        rc.save_auth_token("old").unwrap();
+        rc.set_user_id(String::from("u1"));
+
+        // Both get rejected, only one resumes
+        let (first, second) = tokio::join!(rc.get("api/v1/me"), rc.get("api/v1/me"));
+        assert_eq!(first.unwrap()["username"], "alice");
+        assert_eq!(second.unwrap()["username"], "alice");
+        rejected.assert_async().await;
+        resume.assert_async().await;
+        accepted.assert_async().await;
+        assert_eq!(rc.saved_auth_token(), "new");
+        assert!(rc.is_logged_in());
+    }
+
+    #[tokio::test]
+    async fn test_apply_room_change() {
+        let mut server = mockito::Server::new_async().await;
+        let room_info = server
+            .mock("GET", "/api/v1/rooms.info?roomId=RANDOM")
+            .with_body(
+                r#"{"success": true, "room": {"_id": "RANDOM", "t": "c", "name": "random"}}"#,
+            )
+            .expect(1)
+            .create_async()
+            .await;
+
+        let (_dir, rc) = test_client(&server.url());
+        rc.save_auth_token("token").unwrap();
+        rc.set_user_id(String::from("u1"));
+        rc.set_joined_channels(vec![Channel::for_test("GENERAL", "general")]);
+        let mut events = rc.subscribe();
+
+        // Added to a room we don't know yet
+        let subscription = Subscription {
+            room_id: String::from("RANDOM"),
+            unread: 3,
+            ..Subscription::default()
+        };
+        rc.apply_room_change(RoomChange::Subscribed(subscription))
+            .await
+            .unwrap();
+        room_info.assert_async().await;
+        match events.try_recv() {
+            Ok(Event::RoomAdded(room)) => {
+                assert_eq!(room.display_name(), "random");
+                assert_eq!(room.subscription().unwrap().unread, 3);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // Renamed, keeping the subscription
+        rc.apply_room_change(RoomChange::Updated(Room::Channel(Channel::for_test(
+            "RANDOM",
+            "off-topic",
+        ))))
+        .await
+        .unwrap();
+        assert!(matches!(events.try_recv(), Ok(Event::RoomUpdated(_))));
+        let room = rc.find_room("RANDOM").unwrap();
+        assert_eq!(room.display_name(), "off-topic");
+        assert_eq!(room.subscription().unwrap().unread, 3);
+
+        // Removed, only announced once
+        for _ in 0..2 {
+            rc.apply_room_change(RoomChange::Left(String::from("RANDOM")))
+                .await
+                .unwrap();
+        }
+        assert!(matches!(events.try_recv(), Ok(Event::RoomRemoved(id)) if id == "RANDOM"));
+        assert!(events.try_recv().is_err());
+        assert!(rc.find_room("RANDOM").is_none());
+        assert!(rc.find_room("GENERAL").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_outbox_failures() {
+        let mut server = mockito::Server::new_async().await;
+        let rate_limited = server
+            .mock("POST", "/api/v1/chat.sendMessage")
+            .with_status(429)
+            .with_body(r#"{"success": false, "errorType": "error-too-many-requests"}"#)
+            .create_async()
+            .await;
+
+        let (_dir, rc) = test_client(&server.url());
+        rc.save_auth_token("token").unwrap();
+        rc.set_user_id(String::from("u1"));
+
+        // Worth trying again later
+        let queued = rc.queue_message("GENERAL", None, "hello").await;
+        assert_eq!(queued.state, OutboxState::Queued);
+        rate_limited.remove_async().await;
+
+        // Not worth it, until the user says so
+        server
+            .mock("POST", "/api/v1/chat.sendMessage")
+            .with_status(400)
+            .with_body(r#"{"success": false, "error": "Message is too long"}"#)
+            .create_async()
+            .await;
+        rc.flush_outbox().await;
+        assert_eq!(
+            rc.outbox()[0].state,
+            OutboxState::Failed(String::from("Message is too long"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_download_resumes() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/file-upload/f1/log.txt")
+            .match_header("cookie", "rc_uid=u1; rc_token=old")
+            .with_status(401)
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/api/v1/login")
+            .with_body(r#"{"status": "success", "data": {"userId": "u1", "authToken": "new"}}"#)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/file-upload/f1/log.txt")
+            .match_header("cookie", "rc_uid=u1; rc_token=new")
+            .with_body("crash")
+            .create_async()
+            .await;
+
+        let (_dir, rc) = test_client(&server.url());
+        rc.save_auth_token("old").unwrap();
+        rc.set_user_id(String::from("u1"));
+
+        let local_path = rc.download("file-upload/f1/log.txt").await.unwrap();
+        assert_eq!(std::fs::read_to_string(local_path).unwrap(), "crash");
+    }
+
+    #[tokio::test]
+    async fn test_logout_expired() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/api/v1/logout")
+            .with_status(401)
+            .with_body(r#"{"status": "error", "message": "You must be logged in to do this."}"#)
+            .create_async()
+            .await;
+        let resume = server
+            .mock("POST", "/api/v1/login")
+            .expect(0)
+            .create_async()
+            .await;
+
+        let (_dir, rc) = test_client(&server.url());
+        rc.save_auth_token("expired").unwrap();
+        rc.set_user_id(String::from("u1"));
+        let mut events = rc.subscribe();
+
+        assert_eq!(rc.logout().await, Ok(()));
+        resume.assert_async().await;
+        assert!(!rc.is_logged_in());
+        assert!(rc.saved_auth_token().is_empty());
+        assert!(matches!(
+            events.try_recv(),
+            Ok(Event::LoginChanged(LoginState::LoggedOut))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_logout_offline() {
+        let mut server = mockito::Server::new_async().await;
+        let logout = server
+            .mock("POST", "/api/v1/logout")
+            .match_header("X-Auth-Token", "token")
+            .with_body(r#"{"status": "success", "data": {}}"#)
+            .create_async()
+            .await;
+
+        let (_dir, rc) = test_client(&server.url());
+        rc.save_auth_token("token").unwrap();
+        rc.set_user_id(String::from("u1"));
+        rc.set_joined_channels(vec![Channel::for_test("GENERAL", "general")]);
+        // Nothing answers chat.sendMessage, so it stays queued
+        let queued = rc.queue_message("GENERAL", None, "hello").await;
+        let mut events = rc.subscribe();
+
+        rc.logout().await.unwrap();
+        logout.assert_async().await;
+        assert!(!rc.is_logged_in());
+        assert!(rc.get_auth_token().is_empty());
+        assert!(rc.get_user_id().is_empty());
+        assert!(rc.rooms().is_empty());
+        assert!(rc.saved_auth_token().is_empty());
+        assert!(rc.outbox().is_empty());
+        assert!(matches!(
+            events.try_recv(),
+            Ok(Event::OutboxDiscarded(id)) if id == queued.id
+        ));
+        assert!(matches!(
+            events.try_recv(),
+            Ok(Event::LoginChanged(LoginState::LoggedOut))
+        ));
+
+        // Until the same account logs back in
+        rc.set_user_id(String::from("u1"));
+        assert_eq!(rc.outbox()[0].id, queued.id);
     }
 }
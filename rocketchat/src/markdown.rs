@@ -0,0 +1,673 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: Sergio Martins
+
+//! Parses messages written in Rocket.Chat's markdown dialect
+//!
+//! Newer servers send an already parsed `md` field, which is preferred.
+//! Otherwise the raw text is parsed, supporting the common subset of the dialect.
+
+/// A block of content, messages are a list of these
+#[derive(Clone, Debug, PartialEq)]
+pub enum Block {
+    Paragraph(Vec<Inline>),
+    /// A fenced code block
+    Code {
+        language: Option<String>,
+        code: String,
+    },
+    Quote(Vec<Block>),
+    List {
+        ordered: bool,
+        items: Vec<Vec<Inline>>,
+    },
+    Heading {
+        level: u8,
+        content: Vec<Inline>,
+    },
+    LineBreak,
+}
+
+/// Content inside a block
+#[derive(Clone, Debug, PartialEq)]
+pub enum Inline {
+    Text(String),
+    Bold(Vec<Inline>),
+    Italic(Vec<Inline>),
+    Strike(Vec<Inline>),
+    Code(String),
+    Link {
+        url: String,
+        label: Vec<Inline>,
+    },
+    /// A username, without the '@'. Can also be "here" or "all".
+    UserMention(String),
+    /// A channel name, without the '#'
+    ChannelMention(String),
+    /// A shortcode, without the colons
+    Emoji(String),
+}
+
+/// Converts the server's `md` field
+/// Fails on unexpected input, in which case the raw text should be parsed instead
+pub fn from_md(md: &serde_json::Value) -> Result<Vec<Block>, String> {
+    md.as_array()
+        .ok_or("md is not an array")?
+        .iter()
+        .map(block_from_md)
+        .collect()
+}
+
+fn block_from_md(node: &serde_json::Value) -> Result<Block, String> {
+    let value = &node["value"];
+    Ok(match node["type"].as_str().unwrap_or_default() {
+        "PARAGRAPH" => Block::Paragraph(inlines_from_md(value)?),
+        "CODE" => Block::Code {
+            language: node["language"]
+                .as_str()
+                .filter(|l| !l.is_empty() && *l != "none")
+                .map(String::from),
+            code: value
+                .as_array()
+                .ok_or("CODE value is not an array")?
+                .iter()
+                .map(|line| plain_text(&line["value"]))
+                .collect::<Result<Vec<_>, String>>()?
+                .join("\n"),
+        },
+        "QUOTE" => Block::Quote(from_md(value)?),
+        "UNORDERED_LIST" | "ORDERED_LIST" | "TASKS" => Block::List {
+            ordered: node["type"] == "ORDERED_LIST",
+            items: value
+                .as_array()
+                .ok_or("list value is not an array")?
+                .iter()
+                .map(|item| inlines_from_md(&item["value"]))
+                .collect::<Result<Vec<_>, String>>()?,
+        },
+        "HEADING" => Block::Heading {
+            level: node["level"].as_u64().unwrap_or(1) as u8,
+            content: inlines_from_md(value)?,
+        },
+        "BIG_EMOJI" => Block::Paragraph(inlines_from_md(value)?),
+        "LINE_BREAK" => Block::LineBreak,
+        other => return Err(format!("unsupported md block {}", other)),
+    })
+}
+
+fn inlines_from_md(nodes: &serde_json::Value) -> Result<Vec<Inline>, String> {
+    match nodes {
+        serde_json::Value::Array(nodes) => nodes.iter().map(inline_from_md).collect(),
+        // Some nodes hold a single child instead of a list
+        node => Ok(vec![inline_from_md(node)?]),
+    }
+}
+
+fn inline_from_md(node: &serde_json::Value) -> Result<Inline, String> {
+    let value = &node["value"];
+    Ok(match node["type"].as_str().unwrap_or_default() {
+        "PLAIN_TEXT" => Inline::Text(plain_text(node)?),
+        "BOLD" => Inline::Bold(inlines_from_md(value)?),
+        "ITALIC" => Inline::Italic(inlines_from_md(value)?),
+        "STRIKE" => Inline::Strike(inlines_from_md(value)?),
+        "INLINE_CODE" => Inline::Code(plain_text(value)?),
+        "LINK" => Inline::Link {
+            url: plain_text(&value["src"])?,
+            label: inlines_from_md(&value["label"])?,
+        },
+        "MENTION_USER" => Inline::UserMention(plain_text(value)?),
+        "MENTION_CHANNEL" => Inline::ChannelMention(plain_text(value)?),
+        "EMOJI" => match node["unicode"].as_str() {
+            Some(unicode) => Inline::Text(String::from(unicode)),
+            None => Inline::Emoji(String::from(
+                node["shortCode"]
+                    .as_str()
+                    .map(Ok)
+                    .unwrap_or_else(|| value["value"].as_str().ok_or("EMOJI without value"))?,
+            )),
+        },
+        other => return Err(format!("unsupported md inline {}", other)),
+    })
+}
+
+fn plain_text(node: &serde_json::Value) -> Result<String, String> {
+    node["value"]
+        .as_str()
+        .map(String::from)
+        .ok_or(format!("expected PLAIN_TEXT, got {}", node))
+}
+
+/// Parses the raw text of a message
+pub fn parse(text: &str) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut lines = text.lines().peekable();
+
+    while let Some(line) = lines.next() {
+        if let Some(language) = line.trim_start().strip_prefix("```") {
+            // Fenced code, which might also be closed on the same line
+            if let Some(code) = language.strip_suffix("```") {
+                blocks.push(Block::Code {
+                    language: None,
+                    code: String::from(code),
+                });
+                continue;
+            }
+
+            let mut code = Vec::new();
+            for line in lines.by_ref() {
+                if line.trim_end().ends_with("```") {
+                    let last = line.trim_end().trim_end_matches("```");
+                    if !last.is_empty() {
+                        code.push(last);
+                    }
+                    break;
+                }
+                code.push(line);
+            }
+            blocks.push(Block::Code {
+                language: Some(language.trim())
+                    .filter(|l| !l.is_empty())
+                    .map(String::from),
+                code: code.join("\n"),
+            });
+        } else if line.starts_with('>') {
+            let mut quoted = vec![quoted_line(line)];
+            while let Some(next) = lines.next_if(|l| l.starts_with('>')) {
+                quoted.push(quoted_line(next));
+            }
+            blocks.push(Block::Quote(parse(&quoted.join("\n"))));
+        } else if let Some((ordered, first)) = list_item(line) {
+            let mut items = vec![parse_inlines(first)];
+            while let Some((_, next)) = lines
+                .next_if(|l| list_item(l).map(|(o, _)| o) == Some(ordered))
+                .and_then(list_item)
+            {
+                items.push(parse_inlines(next));
+            }
+            blocks.push(Block::List { ordered, items });
+        } else if let Some((level, content)) = heading(line) {
+            blocks.push(Block::Heading {
+                level,
+                content: parse_inlines(content),
+            });
+        } else if line.trim().is_empty() {
+            blocks.push(Block::LineBreak);
+        } else {
+            blocks.push(Block::Paragraph(parse_inlines(line)));
+        }
+    }
+
+    blocks
+}
+
+fn quoted_line(line: &str) -> &str {
+    let line = line.strip_prefix('>').unwrap_or(line);
+    line.strip_prefix(' ').unwrap_or(line)
+}
+
+/// Returns whether the item is ordered, and its content
+fn list_item(line: &str) -> Option<(bool, &str)> {
+    if let Some(content) = line.strip_prefix("- ").or(line.strip_prefix("* ")) {
+        return Some((false, content));
+    }
+
+    let digits = line.chars().take_while(|c| c.is_ascii_digit()).count();
+    if digits > 0 {
+        return line[digits..].strip_prefix(". ").map(|c| (true, c));
+    }
+
+    None
+}
+
+fn heading(line: &str) -> Option<(u8, &str)> {
+    let level = line.chars().take_while(|c| *c == '#').count();
+    if (1..=4).contains(&level) {
+        line[level..].strip_prefix(' ').map(|c| (level as u8, c))
+    } else {
+        None
+    }
+}
+
+/// Parses the content of a single line
+pub fn parse_inlines(text: &str) -> Vec<Inline> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut inlines = Vec::new();
+    let mut pending_text = String::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        if let Some((inline, next)) = parse_inline_at(&chars, i) {
+            if !pending_text.is_empty() {
+                inlines.push(Inline::Text(std::mem::take(&mut pending_text)));
+            }
+            inlines.push(inline);
+            i = next;
+        } else {
+            pending_text.push(chars[i]);
+            i += 1;
+        }
+    }
+
+    if !pending_text.is_empty() {
+        inlines.push(Inline::Text(pending_text));
+    }
+
+    inlines
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric()
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '.' || c == '_' || c == '-'
+}
+
+fn is_shortcode_char(c: char) -> bool {
+    c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '+' || c == '-'
+}
+
+/// Tries to parse something other than text at position i
+/// Returns the inline and where parsing should continue
+fn parse_inline_at(chars: &[char], i: usize) -> Option<(Inline, usize)> {
+    let at_word_start = i == 0 || !is_word_char(chars[i - 1]);
+    let c = chars[i];
+
+    match c {
+        '*' | '_' | '~' if at_word_start => {
+            let end = find_closing(chars, i + 1, c)?;
+            let content: String = chars[i + 1..end].iter().collect();
+            let content = parse_inlines(&content);
+            let inline = match c {
+                '*' => Inline::Bold(content),
+                '_' => Inline::Italic(content),
+                _ => Inline::Strike(content),
+            };
+            Some((inline, end + 1))
+        }
+        '`' => {
+            let end = (i + 1..chars.len()).find(|j| chars[*j] == '`')?;
+            if end == i + 1 {
+                return None;
+            }
+            Some((Inline::Code(chars[i + 1..end].iter().collect()), end + 1))
+        }
+        '[' => {
+            let label_end = (i + 1..chars.len()).find(|j| chars[*j] == ']')?;
+            if chars.get(label_end + 1) != Some(&'(') {
+                return None;
+            }
+            let url_end = (label_end + 2..chars.len()).find(|j| chars[*j] == ')')?;
+            let label: String = chars[i + 1..label_end].iter().collect();
+            Some((
+                Inline::Link {
+                    url: chars[label_end + 2..url_end].iter().collect(),
+                    label: parse_inlines(&label),
+                },
+                url_end + 1,
+            ))
+        }
+        '@' | '#' if i == 0 || chars[i - 1].is_whitespace() => {
+            let end = (i + 1..chars.len())
+                .find(|j| !is_name_char(chars[*j]))
+                .unwrap_or(chars.len());
+            // Names can't end with punctuation, so "@sergio." mentions sergio
+            let end = (i + 1..end)
+                .rev()
+                .find(|j| chars[*j].is_alphanumeric())
+                .map(|j| j + 1)?;
+            let name: String = chars[i + 1..end].iter().collect();
+            let inline = if c == '@' {
+                Inline::UserMention(name)
+            } else {
+                Inline::ChannelMention(name)
+            };
+            Some((inline, end))
+        }
+        ':' => {
+            let end = (i + 1..chars.len()).find(|j| !is_shortcode_char(chars[*j]))?;
+            if chars[end] != ':' || end == i + 1 {
+                return None;
+            }
+            Some((Inline::Emoji(chars[i + 1..end].iter().collect()), end + 1))
+        }
+        'h' if at_word_start => {
+            let rest: String = chars[i..].iter().take(8).collect();
+            if !rest.starts_with("http://") && !rest.starts_with("https://") {
+                return None;
+            }
+            let end = (i..chars.len())
+                .find(|j| chars[*j].is_whitespace())
+                .unwrap_or(chars.len());
+            let url: String = chars[i..end].iter().collect();
+            Some((
+                Inline::Link {
+                    url: url.clone(),
+                    label: vec![Inline::Text(url)],
+                },
+                end,
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// Finds the delimiter closing an emphasis started right before `start`
+fn find_closing(chars: &[char], start: usize, delimiter: char) -> Option<usize> {
+    if start >= chars.len() || chars[start].is_whitespace() || chars[start] == delimiter {
+        return None;
+    }
+
+    (start + 1..chars.len()).find(|j| {
+        chars[*j] == delimiter
+            && !chars[*j - 1].is_whitespace()
+            && chars.get(*j + 1).map(|c| !is_word_char(*c)).unwrap_or(true)
+    })
+}
+
+/// Renders the AST as text, without any markup
+pub fn to_plain_text(blocks: &[Block]) -> String {
+    blocks
+        .iter()
+        .map(|block| match block {
+            Block::Paragraph(content) | Block::Heading { content, .. } => {
+                inlines_to_plain_text(content)
+            }
+            Block::Code { code, .. } => code.clone(),
+            Block::Quote(blocks) => to_plain_text(blocks),
+            Block::List { items, .. } => items
+                .iter()
+                .map(|item| inlines_to_plain_text(item))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            Block::LineBreak => String::new(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn inlines_to_plain_text(inlines: &[Inline]) -> String {
+    inlines
+        .iter()
+        .map(|inline| match inline {
+            Inline::Text(text) | Inline::Code(text) => text.clone(),
+            Inline::Bold(content) | Inline::Italic(content) | Inline::Strike(content) => {
+                inlines_to_plain_text(content)
+            }
+            Inline::Link { url, label } => {
+                let label = inlines_to_plain_text(label);
+                if label == *url {
+                    label
+                } else {
+                    format!("{} ({})", label, url)
+                }
+            }
+            Inline::UserMention(name) => format!("@{}", name),
+            Inline::ChannelMention(name) => format!("#{}", name),
+            Inline::Emoji(shortcode) => format!(":{}:", shortcode),
+        })
+        .collect()
+}
+
+/// Escapes text for use inside HTML elements and attribute values
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Renders the AST as HTML, with all text escaped
+pub fn to_html(blocks: &[Block]) -> String {
+    blocks
+        .iter()
+        .map(|block| match block {
+            Block::Paragraph(content) => format!("<p>{}</p>", inlines_to_html(content)),
+            Block::Code { language, code } => match language {
+                Some(language) => format!(
+                    "<pre><code class=\"language-{}\">{}</code></pre>",
+                    escape_html(language),
+                    escape_html(code)
+                ),
+                None => format!("<pre><code>{}</code></pre>", escape_html(code)),
+            },
+            Block::Quote(blocks) => format!("<blockquote>{}</blockquote>", to_html(blocks)),
+            Block::List { ordered, items } => {
+                let tag = if *ordered { "ol" } else { "ul" };
+                let items: String = items
+                    .iter()
+                    .map(|item| format!("<li>{}</li>", inlines_to_html(item)))
+                    .collect();
+                format!("<{}>{}</{}>", tag, items, tag)
+            }
+            Block::Heading { level, content } => {
+                let level = level.clamp(&1, &6);
+                format!("<h{}>{}</h{}>", level, inlines_to_html(content), level)
+            }
+            Block::LineBreak => String::from("<br>"),
+        })
+        .collect()
+}
+
+/// Whether a link may go into an href: http, https, mailto or relative
+fn is_safe_url(url: &str) -> bool {
+    // Browsers drop these before looking at the scheme, so "java\tscript:" is javascript:
+    let url: String = url
+        .trim_start_matches(|c: char| c.is_ascii_control() || c == ' ')
+        .chars()
+        .filter(|c| !matches!(c, '\t' | '\n' | '\r'))
+        .collect();
+    match url.find([':', '/', '?', '#']) {
+        Some(end) if url[end..].starts_with(':') => {
+            let scheme = url[..end].to_ascii_lowercase();
+            matches!(scheme.as_str(), "http" | "https" | "mailto")
+        }
+        _ => true,
+    }
+}
+
+pub fn inlines_to_html(inlines: &[Inline]) -> String {
+    inlines
+        .iter()
+        .map(|inline| match inline {
+            Inline::Text(text) => escape_html(text),
+            Inline::Bold(content) => format!("<strong>{}</strong>", inlines_to_html(content)),
+            Inline::Italic(content) => format!("<em>{}</em>", inlines_to_html(content)),
+            Inline::Strike(content) => format!("<del>{}</del>", inlines_to_html(content)),
+            Inline::Code(code) => format!("<code>{}</code>", escape_html(code)),
+            Inline::Link { url, label } if is_safe_url(url) => format!(
+                "<a href=\"{}\">{}</a>",
+                escape_html(url),
+                inlines_to_html(label)
+            ),
+            // javascript: and the like, shown but not clickable
+            Inline::Link { label, .. } => inlines_to_html(label),
+            Inline::UserMention(name) => {
+                format!("<span class=\"mention\">@{}</span>", escape_html(name))
+            }
+            Inline::ChannelMention(name) => {
+                format!("<span class=\"mention\">#{}</span>", escape_html(name))
+            }
+            Inline::Emoji(shortcode) => match crate::emoji::unicode_for(shortcode) {
+                Some(emoji) => String::from(emoji),
+                None => format!(":{}:", escape_html(shortcode)),
+            },
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Inline {
+        Inline::Text(String::from(s))
+    }
+
+    #[test]
+    fn test_parse_inlines() {
+        assert_eq!(
+            parse_inlines("hi *bold* _it_ ~gone~ `code`"),
+            vec![
+                text("hi "),
+                Inline::Bold(vec![text("bold")]),
+                text(" "),
+                Inline::Italic(vec![text("it")]),
+                text(" "),
+                Inline::Strike(vec![text("gone")]),
+                text(" "),
+                Inline::Code(String::from("code")),
+            ]
+        );
+
+        assert_eq!(
+            parse_inlines("@sergio. see #general :thumbsup:"),
+            vec![
+                Inline::UserMention(String::from("sergio")),
+                text(". see "),
+                Inline::ChannelMention(String::from("general")),
+                text(" "),
+                Inline::Emoji(String::from("thumbsup")),
+            ]
+        );
+
+        assert_eq!(
+            parse_inlines("[the docs](https://docs.rocket.chat) or https://x.org"),
+            vec![
+                Inline::Link {
+                    url: String::from("https://docs.rocket.chat"),
+                    label: vec![text("the docs")],
+                },
+                text(" or "),
+                Inline::Link {
+                    url: String::from("https://x.org"),
+                    label: vec![text("https://x.org")],
+                },
+            ]
+        );
+
+        // Not markup
+        assert_eq!(
+            parse_inlines("snake_case_name 2 * 3 * 4 me@example.com 10:30"),
+            vec![text("snake_case_name 2 * 3 * 4 me@example.com 10:30")]
+        );
+    }
+
+    #[test]
+    fn test_parse_blocks() {
+        let blocks = parse("> quoted *text*\n```rust\nfn main() {}\n```\n- one\n- two\n\nbye");
+        assert_eq!(
+            blocks,
+            vec![
+                Block::Quote(vec![Block::Paragraph(vec![
+                    text("quoted "),
+                    Inline::Bold(vec![text("text")]),
+                ])]),
+                Block::Code {
+                    language: Some(String::from("rust")),
+                    code: String::from("fn main() {}"),
+                },
+                Block::List {
+                    ordered: false,
+                    items: vec![vec![text("one")], vec![text("two")]],
+                },
+                Block::LineBreak,
+                Block::Paragraph(vec![text("bye")]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_from_md() {
+        let md = serde_json::json!([
+            {
+                "type": "PARAGRAPH",
+                "value": [
+                    { "type": "PLAIN_TEXT", "value": "hi " },
+                    { "type": "MENTION_USER", "value": { "type": "PLAIN_TEXT", "value": "sergio" } },
+                    { "type": "BOLD", "value": [ { "type": "PLAIN_TEXT", "value": "!" } ] },
+                    { "type": "EMOJI", "value": { "type": "PLAIN_TEXT", "value": "smile" }, "shortCode": "smile" },
+                    {
+                        "type": "LINK",
+                        "value": {
+                            "src": { "type": "PLAIN_TEXT", "value": "https://x.org" },
+                            "label": [ { "type": "PLAIN_TEXT", "value": "x" } ]
+                        }
+                    }
+                ]
+            },
+            {
+                "type": "CODE",
+                "language": "none",
+                "value": [
+                    { "type": "CODE_LINE", "value": { "type": "PLAIN_TEXT", "value": "a" } },
+                    { "type": "CODE_LINE", "value": { "type": "PLAIN_TEXT", "value": "b" } }
+                ]
+            }
+        ]);
+
+        let blocks = from_md(&md).unwrap();
+        assert_eq!(
+            blocks,
+            vec![
+                Block::Paragraph(vec![
+                    text("hi "),
+                    Inline::UserMention(String::from("sergio")),
+                    Inline::Bold(vec![text("!")]),
+                    Inline::Emoji(String::from("smile")),
+                    Inline::Link {
+                        url: String::from("https://x.org"),
+                        label: vec![text("x")],
+                    },
+                ]),
+                Block::Code {
+                    language: None,
+                    code: String::from("a\nb"),
+                },
+            ]
+        );
+        assert_eq!(
+            to_plain_text(&blocks),
+            "hi @sergio!:smile:x (https://x.org)\na\nb"
+        );
+
+        assert!(from_md(&serde_json::json!([{ "type": "SOMETHING_NEW" }])).is_err());
+    }
+
+    #[test]
+    fn test_to_html() {
+        let blocks = parse("> *hi* <b>\n- [docs](https://x.org?a=1&b=2)\n```\nx < y\n```");
+        assert_eq!(
+            to_html(&blocks),
+            "<blockquote><p><strong>hi</strong> &lt;b&gt;</p></blockquote>\
+             <ul><li><a href=\"https://x.org?a=1&amp;b=2\">docs</a></li></ul>\
+             <pre><code>x &lt; y</code></pre>"
+        );
+
+        let link = |url: &str| {
+            inlines_to_html(&[Inline::Link {
+                url: String::from(url),
+                label: vec![text("click")],
+            }])
+        };
+        assert_eq!(link("javascript:alert(1)"), "click");
+        assert_eq!(link(" JavaScript:alert(1)"), "click");
+        assert_eq!(link("java\tscript:alert(1)"), "click");
+        assert_eq!(link("data:text/html,hi"), "click");
+        assert_eq!(
+            link("mailto:a@x.org"),
+            "<a href=\"mailto:a@x.org\">click</a>"
+        );
+        assert_eq!(
+            link("/channel/general?a=b:c"),
+            "<a href=\"/channel/general?a=b:c\">click</a>"
+        );
+    }
+}
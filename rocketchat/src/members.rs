@@ -0,0 +1,181 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: Sergio Martins
+
+/// Whether a user is around
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UserStatus {
+    Online,
+    Away,
+    Busy,
+    Offline,
+}
+
+impl UserStatus {
+    /// Parses the numbers the realtime API uses for statuses
+    pub fn from_code(code: u64) -> UserStatus {
+        match code {
+            1 => UserStatus::Online,
+            2 => UserStatus::Away,
+            3 => UserStatus::Busy,
+            _ => UserStatus::Offline,
+        }
+    }
+
+    pub fn parse(s: &str) -> UserStatus {
+        match s {
+            "online" => UserStatus::Online,
+            "away" => UserStatus::Away,
+            "busy" => UserStatus::Busy,
+            _ => UserStatus::Offline,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UserStatus::Online => "online",
+            UserStatus::Away => "away",
+            UserStatus::Busy => "busy",
+            UserStatus::Offline => "offline",
+        }
+    }
+}
+
+/// Someone in a room
+#[derive(Clone, Debug, PartialEq)]
+pub struct Member {
+    pub id: String,
+    pub username: String,
+    pub name: String,
+    pub status: UserStatus,
+}
+
+impl Member {
+    fn from_json(m: &serde_json::Value) -> Result<Member, String> {
+        Ok(Member {
+            id: String::from(m["_id"].as_str().ok_or("member _id is missing")?),
+            username: String::from(m["username"].as_str().unwrap_or_default()),
+            name: String::from(m["name"].as_str().unwrap_or_default()),
+            status: UserStatus::parse(m["status"].as_str().unwrap_or_default()),
+        })
+    }
+}
+
+/// One page of room members
+#[derive(Clone, Debug, PartialEq)]
+pub struct MembersPage {
+    pub members: Vec<Member>,
+    /// Number of members across all pages
+    pub total: u64,
+}
+
+impl MembersPage {
+    pub fn from_json(body: &serde_json::Value) -> Result<MembersPage, String> {
+        let members = body["members"]
+            .as_array()
+            .ok_or("members is missing")?
+            .iter()
+            .map(Member::from_json)
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(MembersPage {
+            total: body["total"].as_u64().unwrap_or(members.len() as u64),
+            members,
+        })
+    }
+}
+
+/// A user with a role in a room
+#[derive(Clone, Debug, PartialEq)]
+pub struct RoomUser {
+    pub id: String,
+    pub username: String,
+}
+
+/// Who has which role in a room
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RoomRoles {
+    pub owners: Vec<RoomUser>,
+    pub moderators: Vec<RoomUser>,
+    pub leaders: Vec<RoomUser>,
+}
+
+impl RoomRoles {
+    /// Parses the response of channels.roles or groups.roles
+    pub fn from_json(body: &serde_json::Value) -> Result<RoomRoles, String> {
+        let mut roles = RoomRoles::default();
+        for entry in body["roles"].as_array().ok_or("roles is missing")? {
+            let user = RoomUser {
+                id: String::from(entry["u"]["_id"].as_str().ok_or("role u._id is missing")?),
+                username: String::from(entry["u"]["username"].as_str().unwrap_or_default()),
+            };
+
+            for role in entry["roles"].as_array().into_iter().flatten() {
+                match role.as_str() {
+                    Some("owner") => roles.owners.push(user.clone()),
+                    Some("moderator") => roles.moderators.push(user.clone()),
+                    Some("leader") => roles.leaders.push(user.clone()),
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(roles)
+    }
+
+    pub fn is_owner(&self, user_id: &str) -> bool {
+        self.owners.iter().any(|u| u.id == user_id)
+    }
+
+    pub fn is_moderator(&self, user_id: &str) -> bool {
+        self.moderators.iter().any(|u| u.id == user_id)
+    }
+
+    /// Whether the user can kick, mute and so on
+    /// Server-wide admins can too, but we don't know about those
+    pub fn can_moderate(&self, user_id: &str) -> bool {
+        self.is_owner(user_id) || self.is_moderator(user_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_members_page_from_json() {
+        let body = serde_json::json!({
+            "members": [
+                { "_id": "u1", "username": "sergio", "name": "Sergio", "status": "online" },
+                { "_id": "u2", "username": "bot", "status": "something-new" }
+            ],
+            "count": 2,
+            "offset": 0,
+            "total": 7,
+            "success": true
+        });
+
+        let page = MembersPage::from_json(&body).unwrap();
+        assert_eq!(page.total, 7);
+        assert_eq!(page.members[0].status, UserStatus::Online);
+        assert_eq!(page.members[1].status, UserStatus::Offline);
+        assert_eq!(page.members[1].name, "");
+    }
+
+    #[test]
+    fn test_room_roles_from_json() {
+        let body = serde_json::json!({
+            "roles": [
+                { "rid": "r1", "u": { "_id": "u1", "username": "sergio" }, "roles": ["owner", "moderator"] },
+                { "rid": "r1", "u": { "_id": "u2", "username": "lead" }, "roles": ["leader"] }
+            ],
+            "success": true
+        });
+
+        let roles = RoomRoles::from_json(&body).unwrap();
+        assert!(roles.is_owner("u1"));
+        assert!(roles.is_moderator("u1"));
+        assert!(roles.can_moderate("u1"));
+        assert!(!roles.can_moderate("u2"));
+        assert_eq!(roles.leaders[0].username, "lead");
+    }
+}
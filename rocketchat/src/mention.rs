@@ -0,0 +1,132 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: Sergio Martins
+
+use crate::Message;
+
+/// Why a message concerns us, most important first
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MentionKind {
+    /// @username
+    Direct,
+    /// @here or @all
+    Group,
+    /// Contains one of our highlight words
+    Highlight,
+}
+
+/// Mentions in a room that we haven't read yet
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MentionCounters {
+    pub direct: u64,
+    pub group: u64,
+    pub highlight: u64,
+}
+
+impl MentionCounters {
+    pub fn add(&mut self, kind: MentionKind) {
+        match kind {
+            MentionKind::Direct => self.direct += 1,
+            MentionKind::Group => self.group += 1,
+            MentionKind::Highlight => self.highlight += 1,
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.direct + self.group + self.highlight
+    }
+}
+
+/// Returns whether `message` mentions `username`, directly, via @here/@all or via a highlight word
+/// Our own messages never count
+pub fn detect(message: &Message, username: &str, highlights: &[String]) -> Option<MentionKind> {
+    if username.is_empty() || message.author_username == username {
+        return None;
+    }
+
+    if message.mentions.iter().any(|m| m == username) {
+        Some(MentionKind::Direct)
+    } else if message.mentions.iter().any(|m| m == "here" || m == "all") {
+        Some(MentionKind::Group)
+    } else if highlights
+        .iter()
+        .any(|word| contains_word(&message.text, word))
+    {
+        Some(MentionKind::Highlight)
+    } else {
+        None
+    }
+}
+
+/// Case insensitive search for `word`, which must not be part of a longer word
+fn contains_word(text: &str, word: &str) -> bool {
+    let word = word.trim().to_lowercase();
+    if word.is_empty() {
+        return false;
+    }
+
+    let text = text.to_lowercase();
+    let is_word_char = |c: char| c.is_alphanumeric() || c == '_';
+    text.match_indices(&word).any(|(start, _)| {
+        let before = text[..start].chars().next_back();
+        let after = text[start + word.len()..].chars().next();
+        !before.is_some_and(is_word_char) && !after.is_some_and(is_word_char)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(author: &str, text: &str, mentions: &[&str]) -> Message {
+        let json = serde_json::json!({
+            "_id": "msg1",
+            "rid": "GENERAL",
+            "msg": text,
+            "u": { "_id": "u1", "username": author },
+            "mentions": mentions
+                .iter()
+                .map(|m| serde_json::json!({ "_id": m, "username": m }))
+                .collect::<Vec<_>>()
+        });
+        Message::from_json(&json).unwrap()
+    }
+
+    #[test]
+    fn test_detect() {
+        let highlights = vec![String::from("Outage")];
+
+        let direct = message("alice", "@sergio @all look", &["sergio", "all"]);
+        assert_eq!(
+            detect(&direct, "sergio", &highlights),
+            Some(MentionKind::Direct)
+        );
+
+        let group = message("alice", "@here standup", &["here"]);
+        assert_eq!(
+            detect(&group, "sergio", &highlights),
+            Some(MentionKind::Group)
+        );
+
+        let highlight = message("alice", "Is this an outage?", &[]);
+        assert_eq!(
+            detect(&highlight, "sergio", &highlights),
+            Some(MentionKind::Highlight)
+        );
+
+        let own = message("sergio", "@here outage", &["here"]);
+        assert_eq!(detect(&own, "sergio", &highlights), None);
+
+        let partial = message("alice", "outages happen", &[]);
+        assert_eq!(detect(&partial, "sergio", &highlights), None);
+    }
+
+    #[test]
+    fn test_mention_counters() {
+        let mut counters = MentionCounters::default();
+        counters.add(MentionKind::Direct);
+        counters.add(MentionKind::Highlight);
+        counters.add(MentionKind::Highlight);
+        assert_eq!(counters.highlight, 2);
+        assert_eq!(counters.total(), 3);
+    }
+}
@@ -0,0 +1,231 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: Sergio Martins
+
+use crate::{markdown, mention, MentionKind};
+use std::collections::BTreeMap;
+
+/// A chat message
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    pub id: String,
+    pub room_id: String,
+    /// The raw text, in Rocket.Chat's markdown dialect
+    pub text: String,
+    pub author_id: String,
+    pub author_username: String,
+    pub timestamp: i64,
+    /// The message this one replies to, if it's part of a thread
+    pub thread_id: Option<String>,
+    pub pinned: bool,
+    /// IDs of the users that starred this message
+    pub starred_by: Vec<String>,
+    /// The text already parsed by the server, only sent by newer servers
+    pub md: Option<serde_json::Value>,
+    /// Usernames mentioned in the text, including "here" and "all"
+    pub mentions: Vec<String>,
+    /// Names of the rooms mentioned in the text
+    pub channels: Vec<String>,
+    pub attachments: Vec<Attachment>,
+    /// Usernames that reacted, by emoji, for example ":tada:"
+    pub reactions: BTreeMap<String, Vec<String>>,
+}
+
+/// A file or image attached to a message
+#[derive(Clone, Debug, PartialEq)]
+pub struct Attachment {
+    pub title: String,
+    pub description: Option<String>,
+    /// Server path of the file, relative to the server URL, see RocketChat::download()
+    pub path: String,
+}
+
+impl Attachment {
+    /// Returns None for attachments without a file, like quotes and link previews
+    pub fn from_json(a: &serde_json::Value) -> Option<Attachment> {
+        let path = a["title_link"]
+            .as_str()
+            .or(a["image_url"].as_str())
+            .filter(|path| path.starts_with('/'))?;
+        Some(Attachment {
+            title: String::from(a["title"].as_str().unwrap_or_default()),
+            description: a["description"].as_str().map(String::from),
+            path: String::from(path.trim_start_matches('/')),
+        })
+    }
+}
+
+impl Message {
+    pub fn from_json(m: &serde_json::Value) -> Result<Message, String> {
+        Ok(Message {
+            id: String::from(m["_id"].as_str().ok_or("message _id is missing")?),
+            room_id: String::from(m["rid"].as_str().ok_or("message rid is missing")?),
+            text: String::from(m["msg"].as_str().unwrap_or_default()),
+            author_id: String::from(m["u"]["_id"].as_str().unwrap_or_default()),
+            author_username: String::from(m["u"]["username"].as_str().unwrap_or_default()),
+            timestamp: timestamp(&m["ts"])?,
+            thread_id: m["tmid"].as_str().map(String::from),
+            pinned: m["pinned"].as_bool().unwrap_or(false),
+            starred_by: names(&m["starred"], "_id"),
+            md: m.get("md").filter(|md| md.is_array()).cloned(),
+            mentions: names(&m["mentions"], "username"),
+            channels: names(&m["channels"], "name"),
+            attachments: m["attachments"]
+                .as_array()
+                .map(|items| items.iter().filter_map(Attachment::from_json).collect())
+                .unwrap_or_default(),
+            reactions: m["reactions"]
+                .as_object()
+                .map(|reactions| {
+                    reactions
+                        .iter()
+                        .map(|(emoji, r)| {
+                            let usernames = r["usernames"]
+                                .as_array()
+                                .map(|names| {
+                                    names
+                                        .iter()
+                                        .filter_map(|n| n.as_str().map(String::from))
+                                        .collect()
+                                })
+                                .unwrap_or_default();
+                            (emoji.clone(), usernames)
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+        })
+    }
+
+    /// Returns the message parsed into blocks of formatted text
+    pub fn ast(&self) -> Vec<markdown::Block> {
+        self.md
+            .as_ref()
+            .and_then(|md| markdown::from_md(md).ok())
+            .unwrap_or_else(|| markdown::parse(&self.text))
+    }
+
+    /// Returns whether this message mentions `username`, see mention::detect()
+    pub fn mention_kind(&self, username: &str, highlights: &[String]) -> Option<MentionKind> {
+        mention::detect(self, username, highlights)
+    }
+
+    pub fn is_starred_by(&self, user_id: &str) -> bool {
+        self.starred_by.iter().any(|id| id == user_id)
+    }
+
+    /// Parses an array of messages, as found in chat.search and friends
+    pub fn list_from_json(messages: &serde_json::Value) -> Result<Vec<Message>, String> {
+        messages
+            .as_array()
+            .ok_or("messages is missing")?
+            .iter()
+            .map(Message::from_json)
+            .collect()
+    }
+}
+
+/// The REST API sends dates as strings, the realtime API as {"$date": milliseconds}
+/// -1 when missing, which only happens in partial updates
+pub(crate) fn timestamp(ts: &serde_json::Value) -> Result<i64, String> {
+    if let Some(millis) = ts["$date"].as_i64() {
+        return Ok(millis / 1000);
+    }
+    ts.as_str()
+        .map(crate::str_to_timestamp)
+        .transpose()
+        .map(|ts| ts.unwrap_or(-1))
+}
+
+/// Collects `key` out of an array of objects, skipping the ones without it
+fn names(array: &serde_json::Value, key: &str) -> Vec<String> {
+    array
+        .as_array()
+        .map(|items| {
+            items
+                .iter()
+                .filter_map(|item| item[key].as_str().map(String::from))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// One page of messages
+#[derive(Clone, Debug, PartialEq)]
+pub struct MessagesPage {
+    pub messages: Vec<Message>,
+    /// Number of messages across all pages
+    pub total: u64,
+}
+
+impl MessagesPage {
+    pub fn from_json(body: &serde_json::Value) -> Result<MessagesPage, String> {
+        let messages = Message::list_from_json(&body["messages"])?;
+        Ok(MessagesPage {
+            total: body["total"].as_u64().unwrap_or(messages.len() as u64),
+            messages,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_from_json() {
+        let json = serde_json::json!({
+            "_id": "msg1",
+            "rid": "GENERAL",
+            "msg": "see https://example.com",
+            "ts": "2022-05-17T14:55:23.276Z",
+            "u": { "_id": "user1", "username": "sergio", "name": "Sergio" },
+            "tmid": "parent",
+            "pinned": true,
+            "starred": [{ "_id": "user2" }],
+            "mentions": [{ "_id": "user2", "username": "alice" }],
+            "channels": [{ "_id": "room2", "name": "support" }],
+            "reactions": { ":tada:": { "usernames": ["alice", "bob"] } },
+            "attachments": [
+                { "title": "log.txt", "title_link": "/file-upload/f1/log.txt", "description": "crash" },
+                { "text": "a quoted message", "message_link": "https://example.com/msg" }
+            ]
+        });
+
+        let message = Message::from_json(&json).unwrap();
+        assert_eq!(message.id, "msg1");
+        assert_eq!(message.room_id, "GENERAL");
+        assert_eq!(message.author_username, "sergio");
+        assert_eq!(message.timestamp, 1652799323);
+        assert_eq!(message.thread_id, Some(String::from("parent")));
+        assert!(message.pinned);
+        assert!(message.is_starred_by("user2"));
+        assert!(!message.is_starred_by("user1"));
+        assert_eq!(message.mentions, vec![String::from("alice")]);
+        assert_eq!(message.channels, vec![String::from("support")]);
+        assert_eq!(
+            message.reactions[":tada:"],
+            vec![String::from("alice"), String::from("bob")]
+        );
+        assert_eq!(
+            message.attachments,
+            vec![Attachment {
+                title: String::from("log.txt"),
+                description: Some(String::from("crash")),
+                path: String::from("file-upload/f1/log.txt"),
+            }]
+        );
+
+        assert_eq!(message.md, None);
+        assert_eq!(message.ast().len(), 1);
+
+        assert!(Message::from_json(&serde_json::json!({ "msg": "no id" })).is_err());
+        assert!(Message::from_json(&serde_json::json!({
+            "_id": "msg2", "rid": "GENERAL", "ts": "yesterday"
+        }))
+        .is_err());
+        assert_eq!(
+            timestamp(&serde_json::json!({ "$date": 1652799323276_i64 })),
+            Ok(1652799323)
+        );
+    }
+}
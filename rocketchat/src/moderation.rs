@@ -0,0 +1,206 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: Sergio Martins
+
+use crate::EndpointFamily;
+
+/// First server version with rooms.muteUser and rooms.unmuteUser
+pub const ROOMS_MUTE_VERSION: (u32, u32) = (6, 8);
+
+/// Something a room owner or moderator can do
+/// User IDs are expected, not usernames
+#[derive(Clone, Debug, PartialEq)]
+pub enum ModerationAction {
+    Kick(String),
+    Invite(String),
+    Mute(String),
+    Unmute(String),
+    AddOwner(String),
+    RemoveOwner(String),
+    AddModerator(String),
+    RemoveModerator(String),
+    SetTopic(String),
+    SetAnnouncement(String),
+    SetDescription(String),
+    SetReadOnly(bool),
+    Archive,
+    Unarchive,
+}
+
+impl ModerationAction {
+    /// Returns the endpoint implementing this action, for a room managed by `family`
+    /// Muting needs legacy_mute_request() instead on servers older than ROOMS_MUTE_VERSION.
+    pub fn endpoint(&self, family: EndpointFamily) -> String {
+        let method = match self {
+            // Muting works the same for every room type
+            ModerationAction::Mute(_) => return String::from("api/v1/rooms.muteUser"),
+            ModerationAction::Unmute(_) => return String::from("api/v1/rooms.unmuteUser"),
+            ModerationAction::Kick(_) => "kick",
+            ModerationAction::Invite(_) => "invite",
+            ModerationAction::AddOwner(_) => "addOwner",
+            ModerationAction::RemoveOwner(_) => "removeOwner",
+            ModerationAction::AddModerator(_) => "addModerator",
+            ModerationAction::RemoveModerator(_) => "removeModerator",
+            ModerationAction::SetTopic(_) => "setTopic",
+            ModerationAction::SetAnnouncement(_) => "setAnnouncement",
+            ModerationAction::SetDescription(_) => "setDescription",
+            ModerationAction::SetReadOnly(_) => "setReadOnly",
+            ModerationAction::Archive => "archive",
+            ModerationAction::Unarchive => "unarchive",
+        };
+
+        format!("api/v1/{}.{}", family.as_str(), method)
+    }
+
+    /// Returns the endpoint and payload muting or unmuting on servers older than ROOMS_MUTE_VERSION
+    /// These go through a method call, which takes a username instead of a user ID.
+    /// None for the other actions, which don't depend on the version.
+    pub fn legacy_mute_request(
+        &self,
+        room_id: &str,
+        username: &str,
+    ) -> Option<(String, serde_json::Value)> {
+        let method = match self {
+            ModerationAction::Mute(_) => "muteUserInRoom",
+            ModerationAction::Unmute(_) => "unmuteUserInRoom",
+            _ => return None,
+        };
+        let message = serde_json::json!({
+            "msg": "method",
+            "id": "1",
+            "method": method,
+            "params": [{ "rid": room_id, "username": username }],
+        });
+        Some((
+            format!("api/v1/method.call/{}", method),
+            serde_json::json!({ "message": message.to_string() }),
+        ))
+    }
+
+    /// Only owners can change the owners and moderators, make rooms read-only, or archive them
+    /// With the default roles that is; moderators lack set-moderator and set-readonly.
+    pub fn requires_owner(&self) -> bool {
+        matches!(
+            self,
+            ModerationAction::AddOwner(_)
+                | ModerationAction::RemoveOwner(_)
+                | ModerationAction::AddModerator(_)
+                | ModerationAction::RemoveModerator(_)
+                | ModerationAction::SetReadOnly(_)
+                | ModerationAction::Archive
+                | ModerationAction::Unarchive
+        )
+    }
+
+    pub fn payload(&self, room_id: &str) -> serde_json::Value {
+        match self {
+            ModerationAction::Kick(user_id)
+            | ModerationAction::Invite(user_id)
+            | ModerationAction::Mute(user_id)
+            | ModerationAction::Unmute(user_id)
+            | ModerationAction::AddOwner(user_id)
+            | ModerationAction::RemoveOwner(user_id)
+            | ModerationAction::AddModerator(user_id)
+            | ModerationAction::RemoveModerator(user_id) => {
+                serde_json::json!({ "roomId": room_id, "userId": user_id })
+            }
+            ModerationAction::SetTopic(topic) => {
+                serde_json::json!({ "roomId": room_id, "topic": topic })
+            }
+            ModerationAction::SetAnnouncement(announcement) => {
+                serde_json::json!({ "roomId": room_id, "announcement": announcement })
+            }
+            ModerationAction::SetDescription(description) => {
+                serde_json::json!({ "roomId": room_id, "description": description })
+            }
+            ModerationAction::SetReadOnly(read_only) => {
+                serde_json::json!({ "roomId": room_id, "readOnly": read_only })
+            }
+            ModerationAction::Archive | ModerationAction::Unarchive => {
+                serde_json::json!({ "roomId": room_id })
+            }
+        }
+    }
+}
+
+/// Checks a method.call response, whose result is JSON inside the `message` string
+pub fn method_call_result(body: &serde_json::Value) -> Result<(), String> {
+    if !body["success"].as_bool().unwrap_or(false) {
+        return Err(String::from(
+            body["error"].as_str().unwrap_or("method call failed"),
+        ));
+    }
+    let result: serde_json::Value = serde_json::from_str(body["message"].as_str().unwrap_or("{}"))
+        .map_err(|e| format!("invalid method result: {}", e))?;
+    match result.get("error") {
+        Some(error) => Err(String::from(
+            error["reason"]
+                .as_str()
+                .or(error["error"].as_str())
+                .unwrap_or("method call failed"),
+        )),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_moderation_action() {
+        let kick = ModerationAction::Kick(String::from("u1"));
+        assert_eq!(kick.endpoint(EndpointFamily::Groups), "api/v1/groups.kick");
+        assert!(!kick.requires_owner());
+        assert_eq!(
+            kick.payload("r1"),
+            serde_json::json!({ "roomId": "r1", "userId": "u1" })
+        );
+
+        let mute = ModerationAction::Mute(String::from("u1"));
+        assert_eq!(
+            mute.endpoint(EndpointFamily::Channels),
+            "api/v1/rooms.muteUser"
+        );
+
+        assert!(ModerationAction::Archive.requires_owner());
+        assert!(ModerationAction::AddModerator(String::from("u1")).requires_owner());
+        assert!(ModerationAction::RemoveModerator(String::from("u1")).requires_owner());
+        assert!(ModerationAction::SetReadOnly(true).requires_owner());
+        assert!(!ModerationAction::SetTopic(String::from("news")).requires_owner());
+        assert!(!mute.requires_owner());
+        assert_eq!(
+            ModerationAction::SetReadOnly(true).payload("r1"),
+            serde_json::json!({ "roomId": "r1", "readOnly": true })
+        );
+    }
+
+    #[test]
+    fn test_legacy_mute() {
+        let (endpoint, payload) = ModerationAction::Mute(String::from("u1"))
+            .legacy_mute_request("r1", "alice")
+            .unwrap();
+        assert_eq!(endpoint, "api/v1/method.call/muteUserInRoom");
+        let message: serde_json::Value =
+            serde_json::from_str(payload["message"].as_str().unwrap()).unwrap();
+        assert_eq!(
+            message["params"],
+            serde_json::json!([{ "rid": "r1", "username": "alice" }])
+        );
+        assert_eq!(
+            ModerationAction::Archive.legacy_mute_request("r1", "alice"),
+            None
+        );
+
+        let ok =
+            serde_json::json!({ "success": true, "message": "{\"msg\":\"result\",\"id\":\"1\"}" });
+        assert_eq!(method_call_result(&ok), Ok(()));
+        let refused = serde_json::json!({
+            "success": true,
+            "message": "{\"msg\":\"result\",\"id\":\"1\",\"error\":{\"error\":\"error-not-allowed\",\"reason\":\"Not allowed\"}}"
+        });
+        assert_eq!(
+            method_call_result(&refused),
+            Err(String::from("Not allowed"))
+        );
+    }
+}
@@ -0,0 +1,351 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: Sergio Martins
+
+//! Messages waiting to be sent, kept on disk so they survive going offline and restarts
+
+use rand::Rng;
+use std::path::PathBuf;
+
+/// Where a queued message is at
+#[derive(Clone, Debug, PartialEq)]
+pub enum OutboxState {
+    Queued,
+    Sending,
+    /// The server has it, and it's no longer in the outbox
+    Sent,
+    /// The server rejected it, with this error. Blocks the room's queue until retried or discarded.
+    Failed(String),
+}
+
+/// A message composed by us, not yet confirmed by the server
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutboxMessage {
+    /// Generated by us and sent along, so the server's copy can be recognized
+    pub id: String,
+    pub room_id: String,
+    pub thread_id: Option<String>,
+    pub text: String,
+    /// When it was composed, in seconds
+    pub queued_at: i64,
+    pub state: OutboxState,
+}
+
+impl OutboxMessage {
+    fn from_json(m: &serde_json::Value) -> Result<OutboxMessage, String> {
+        let state = match m["state"].as_str() {
+            // It might not have made it, a retry will tell
+            Some("queued") | Some("sending") => OutboxState::Queued,
+            Some("failed") => {
+                OutboxState::Failed(String::from(m["error"].as_str().unwrap_or_default()))
+            }
+            other => return Err(format!("unexpected outbox state {:?}", other)),
+        };
+
+        Ok(OutboxMessage {
+            id: String::from(m["id"].as_str().ok_or("outbox id is missing")?),
+            room_id: String::from(m["room_id"].as_str().ok_or("outbox room_id is missing")?),
+            thread_id: m["thread_id"].as_str().map(String::from),
+            text: String::from(m["text"].as_str().unwrap_or_default()),
+            queued_at: m["queued_at"].as_i64().unwrap_or_default(),
+            state,
+        })
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let (state, error) = match &self.state {
+            OutboxState::Queued => ("queued", None),
+            OutboxState::Sending => ("sending", None),
+            OutboxState::Sent => ("sent", None),
+            OutboxState::Failed(error) => ("failed", Some(error)),
+        };
+        serde_json::json!({
+            "id": self.id,
+            "room_id": self.room_id,
+            "thread_id": self.thread_id,
+            "text": self.text,
+            "queued_at": self.queued_at,
+            "state": state,
+            "error": error,
+        })
+    }
+
+    /// The message object for chat.sendMessage
+    pub(crate) fn to_request(&self) -> serde_json::Value {
+        let mut message =
+            serde_json::json!({ "_id": self.id, "rid": self.room_id, "msg": self.text });
+        if let Some(thread_id) = &self.thread_id {
+            message["tmid"] = serde_json::json!(thread_id);
+        }
+        message
+    }
+}
+
+/// Returns a random ID in the server's format: 17 characters, without lookalikes
+pub(crate) fn generate_id() -> String {
+    const ALPHABET: &[u8] = b"23456789ABCDEFGHJKLMNPQRSTWXYZabcdefghijkmnopqrstuvwxyz";
+    let mut rng = rand::thread_rng();
+    (0..17)
+        .map(|_| ALPHABET[rng.gen_range(0..ALPHABET.len())] as char)
+        .collect()
+}
+
+/// The queued messages of one account, oldest first, and the file they're saved to
+/// Other accounts' messages are parked next to it, until they log in again.
+pub(crate) struct Outbox {
+    file: PathBuf,
+    /// Whose messages these are, empty before anyone logged in
+    user_id: String,
+    messages: Vec<OutboxMessage>,
+}
+
+/// Returns the owner and messages saved in `file`
+fn read(file: &PathBuf) -> Result<(String, Vec<OutboxMessage>), String> {
+    let json: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(file).map_err(|e| e.to_string())?)
+            .map_err(|e| e.to_string())?;
+    let messages = json["messages"]
+        .as_array()
+        .ok_or("messages is missing")?
+        .iter()
+        .map(OutboxMessage::from_json)
+        .collect::<Result<_, _>>()?;
+    Ok((
+        String::from(json["user_id"].as_str().unwrap_or_default()),
+        messages,
+    ))
+}
+
+fn write(file: &PathBuf, user_id: &str, messages: &[OutboxMessage]) {
+    let json = serde_json::json!({
+        "user_id": user_id,
+        "messages": messages.iter().map(OutboxMessage::to_json).collect::<Vec<_>>(),
+    });
+    let result = match file.parent() {
+        Some(dir) => crate::paths::ensure_created(dir).map(|_| ()),
+        None => Ok(()),
+    }
+    .and_then(|_| std::fs::write(file, json.to_string()).map_err(|e| e.to_string()));
+    if let Err(e) = result {
+        log::warn!("Couldn't save the outbox to {}: {}", file.display(), e);
+    }
+}
+
+impl Outbox {
+    /// Loads what was queued in a previous run. A missing or unreadable file means an empty outbox.
+    pub fn load(file: PathBuf) -> Outbox {
+        let (user_id, messages) = if file.exists() {
+            read(&file).unwrap_or_else(|e| {
+                log::warn!("Ignoring outbox {}: {}", file.display(), e);
+                Default::default()
+            })
+        } else {
+            Default::default()
+        };
+        Outbox {
+            file,
+            user_id,
+            messages,
+        }
+    }
+
+    fn save(&self) {
+        write(&self.file, &self.user_id, &self.messages);
+    }
+
+    /// Where `user_id`'s messages wait while someone else is logged in
+    fn parked_file(&self, user_id: &str) -> PathBuf {
+        let stem = self.file.file_stem().unwrap_or_default().to_string_lossy();
+        self.file
+            .with_file_name(format!("{}-{}.json", stem, user_id))
+    }
+
+    /// Hands the outbox to another account, or to nobody when logging out
+    /// Parks the previous owner's messages and brings back the new owner's. Messages queued
+    /// before anyone logged in go to whoever does first. Returns the parked and restored messages.
+    pub fn set_user(&mut self, user_id: &str) -> (Vec<OutboxMessage>, Vec<OutboxMessage>) {
+        if self.user_id == user_id {
+            return Default::default();
+        }
+
+        let mut parked = Vec::new();
+        if !self.user_id.is_empty() {
+            parked = std::mem::take(&mut self.messages);
+        }
+        if !parked.is_empty() {
+            let file = self.parked_file(&self.user_id);
+            let mut messages = read(&file).map(|(_, m)| m).unwrap_or_default();
+            messages.extend(parked.iter().cloned());
+            write(&file, &self.user_id, &messages);
+        }
+
+        let mut restored = Vec::new();
+        if !user_id.is_empty() {
+            let file = self.parked_file(user_id);
+            if file.exists() {
+                match read(&file) {
+                    Ok((_, messages)) => restored = messages,
+                    Err(e) => log::warn!("Ignoring outbox {}: {}", file.display(), e),
+                }
+                if let Err(e) = std::fs::remove_file(&file) {
+                    log::warn!("Couldn't remove {}: {}", file.display(), e);
+                }
+            }
+        }
+
+        self.user_id = String::from(user_id);
+        // Older than anything queued before logging in
+        self.messages.splice(0..0, restored.iter().cloned());
+        self.save();
+        (parked, restored)
+    }
+
+    pub fn messages(&self) -> Vec<OutboxMessage> {
+        self.messages.clone()
+    }
+
+    pub fn push(&mut self, message: OutboxMessage) {
+        self.messages.push(message);
+        self.save();
+    }
+
+    /// Returns the room's next message to send, unless a failed one is holding the queue
+    pub fn next_queued(&self, room_id: &str) -> Option<OutboxMessage> {
+        self.messages
+            .iter()
+            .find(|m| m.room_id == room_id)
+            .filter(|m| m.state == OutboxState::Queued)
+            .cloned()
+    }
+
+    /// Rooms with messages waiting to be sent
+    pub fn rooms(&self) -> Vec<String> {
+        let mut rooms: Vec<String> = Vec::new();
+        for message in &self.messages {
+            if !rooms.contains(&message.room_id) {
+                rooms.push(message.room_id.clone());
+            }
+        }
+        rooms
+    }
+
+    /// Returns the updated message, None if it's not in the outbox
+    /// Sent messages are removed.
+    pub fn set_state(&mut self, id: &str, state: OutboxState) -> Option<OutboxMessage> {
+        let index = self.messages.iter().position(|m| m.id == id)?;
+        self.messages[index].state = state;
+        let message = if self.messages[index].state == OutboxState::Sent {
+            self.messages.remove(index)
+        } else {
+            self.messages[index].clone()
+        };
+        self.save();
+        Some(message)
+    }
+
+    pub fn remove(&mut self, id: &str) -> Option<OutboxMessage> {
+        let index = self.messages.iter().position(|m| m.id == id)?;
+        let message = self.messages.remove(index);
+        self.save();
+        Some(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(id: &str, room_id: &str) -> OutboxMessage {
+        OutboxMessage {
+            id: String::from(id),
+            room_id: String::from(room_id),
+            thread_id: None,
+            text: String::from("hi"),
+            queued_at: 1652799323,
+            state: OutboxState::Queued,
+        }
+    }
+
+    #[test]
+    fn test_outbox_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut outbox = Outbox::load(dir.path().join("outbox.json"));
+        outbox.push(message("m1", "GENERAL"));
+        outbox.push(message("m2", "random"));
+        outbox.push(message("m3", "GENERAL"));
+        assert_eq!(outbox.rooms(), vec!["GENERAL", "random"]);
+        assert_eq!(outbox.next_queued("GENERAL").unwrap().id, "m1");
+
+        // A failure holds back the messages queued after it
+        outbox.set_state("m1", OutboxState::Failed(String::from("not allowed")));
+        assert_eq!(outbox.next_queued("GENERAL"), None);
+        assert_eq!(outbox.next_queued("random").unwrap().id, "m2");
+
+        outbox.remove("m1");
+        assert_eq!(outbox.next_queued("GENERAL").unwrap().id, "m3");
+        assert_eq!(
+            outbox.set_state("m3", OutboxState::Sent).unwrap().state,
+            OutboxState::Sent
+        );
+        assert_eq!(outbox.next_queued("GENERAL"), None);
+        assert_eq!(outbox.set_state("m3", OutboxState::Queued), None);
+    }
+
+    #[test]
+    fn test_outbox_persistence() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("data").join("outbox.json");
+        let mut outbox = Outbox::load(file.clone());
+        let mut reply = message("m1", "GENERAL");
+        reply.thread_id = Some(String::from("parent"));
+        outbox.push(reply.clone());
+        outbox.push(message("m2", "GENERAL"));
+        outbox.set_state("m1", OutboxState::Sending);
+        outbox.set_state("m2", OutboxState::Failed(String::from("too long")));
+
+        // Interrupted sends are retried
+        let loaded = Outbox::load(file).messages();
+        assert_eq!(loaded[0], reply);
+        assert_eq!(
+            loaded[1].state,
+            OutboxState::Failed(String::from("too long"))
+        );
+
+        assert_eq!(reply.to_request()["tmid"], "parent");
+    }
+
+    #[test]
+    fn test_outbox_users() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("outbox.json");
+        let mut outbox = Outbox::load(file.clone());
+
+        // Queued before logging in
+        outbox.push(message("m1", "GENERAL"));
+        assert_eq!(outbox.set_user("u1"), (Vec::new(), Vec::new()));
+        assert_eq!(outbox.messages().len(), 1);
+
+        let (parked, restored) = outbox.set_user("u2");
+        assert_eq!(parked, vec![message("m1", "GENERAL")]);
+        assert!(restored.is_empty());
+        assert!(outbox.messages().is_empty());
+        outbox.push(message("m2", "GENERAL"));
+
+        // Logging out parks them too, and restarting keeps them parked
+        outbox.set_user("");
+        let mut outbox = Outbox::load(file);
+        assert!(outbox.messages().is_empty());
+
+        let (parked, restored) = outbox.set_user("u1");
+        assert!(parked.is_empty());
+        assert_eq!(restored, vec![message("m1", "GENERAL")]);
+        outbox.set_user("u2");
+        assert_eq!(outbox.messages(), vec![message("m2", "GENERAL")]);
+    }
+
+    #[test]
+    fn test_generate_id() {
+        let id = generate_id();
+        assert_eq!(id.len(), 17);
+        assert_ne!(id, generate_id());
+    }
+}
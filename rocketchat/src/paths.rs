@@ -0,0 +1,129 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: Sergio Martins
+
+use std::path::{Path, PathBuf};
+
+/// Where we keep our files
+/// Directories are created when first written to
+#[derive(Clone, Debug, PartialEq)]
+pub struct Paths {
+    /// Settings and the saved login token
+    pub config_dir: PathBuf,
+    /// Downloads that can be fetched again if deleted
+    pub cache_dir: PathBuf,
+    /// State that should survive restarts
+    pub data_dir: PathBuf,
+}
+
+impl Paths {
+    /// The platform's standard directories, under an "rc-slint" subdirectory
+    /// Fails on platforms without them, like Android, where the app has to provide its own
+    pub fn platform_default() -> Result<Paths, String> {
+        Self::for_app("rc-slint")
+    }
+
+    /// The platform's standard directories, under an `app` subdirectory
+    pub fn for_app(app: &str) -> Result<Paths, String> {
+        let dir = |dir: Option<PathBuf>, kind: &str| {
+            dir.map(|dir| dir.join(app))
+                .ok_or(format!("couldn't find the {} dir", kind))
+        };
+
+        Ok(Paths {
+            config_dir: dir(dirs_next::config_dir(), "config")?,
+            cache_dir: dir(dirs_next::cache_dir(), "cache")?,
+            data_dir: dir(dirs_next::data_dir(), "data")?,
+        })
+    }
+
+    /// A subdirectory of each for `url`, so that tokens and queued messages of different servers
+    /// don't get mixed up
+    pub fn for_server(&self, url: &str) -> Paths {
+        let server = server_name(url);
+        Paths {
+            config_dir: self.config_dir.join(&server),
+            cache_dir: self.cache_dir.join(&server),
+            data_dir: self.data_dir.join(&server),
+        }
+    }
+
+    /// Everything under a single directory, for tests and sandboxed environments
+    pub fn in_dir(dir: &Path) -> Paths {
+        Paths {
+            config_dir: dir.join("config"),
+            cache_dir: dir.join("cache"),
+            data_dir: dir.join("data"),
+        }
+    }
+
+    pub(crate) fn auth_token_file(&self) -> PathBuf {
+        self.config_dir.join(".auth_token")
+    }
+
+    /// Accounts on the same server take turns with it, see Outbox::set_user()
+    pub(crate) fn outbox_file(&self) -> PathBuf {
+        self.data_dir.join("outbox.json")
+    }
+}
+
+/// Turns a server URL into something usable as a file name, for example chat.example.com_3000
+fn server_name(url: &str) -> String {
+    url.split("://")
+        .last()
+        .unwrap_or(url)
+        .trim_end_matches('/')
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Creates `dir` if needed, and returns it
+pub(crate) fn ensure_created(dir: &Path) -> Result<&Path, String> {
+    std::fs::create_dir_all(dir)
+        .map_err(|e| format!("couldn't create {}: {}", dir.display(), e))?;
+    Ok(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_in_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = Paths::in_dir(dir.path());
+        assert_eq!(
+            paths.auth_token_file(),
+            dir.path().join("config").join(".auth_token")
+        );
+
+        assert!(!paths.cache_dir.exists());
+        ensure_created(&paths.cache_dir).unwrap();
+        assert!(paths.cache_dir.is_dir());
+    }
+
+    #[test]
+    fn test_for_server() {
+        let paths = Paths::in_dir(Path::new("/tmp")).for_server("https://chat.example.com:3000/");
+        assert_eq!(
+            paths.config_dir,
+            Path::new("/tmp/config/chat.example.com_3000")
+        );
+        assert_eq!(
+            Paths::in_dir(Path::new("/tmp"))
+                .for_server("http://chat.example.com")
+                .data_dir,
+            Path::new("/tmp/data/chat.example.com")
+        );
+        assert_eq!(
+            paths.outbox_file(),
+            Path::new("/tmp/data/chat.example.com_3000/outbox.json")
+        );
+    }
+}
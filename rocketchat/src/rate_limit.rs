@@ -0,0 +1,184 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: Sergio Martins
+
+use rand::Rng;
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+/// How many times an idempotent request is retried before giving up
+pub const MAX_RETRIES: u32 = 5;
+
+const BASE_BACKOFF_MS: u64 = 500;
+const MAX_BACKOFF_MS: u64 = 30_000;
+
+/// The rate-limit budget the server reported for an endpoint
+#[derive(Clone, Debug, PartialEq)]
+pub struct RateLimit {
+    /// Requests allowed per period
+    pub limit: u64,
+    /// Requests left in the current period
+    pub remaining: u64,
+    /// When the period ends, in milliseconds since the epoch
+    pub reset: i64,
+}
+
+impl RateLimit {
+    /// Parses the X-RateLimit-* headers. None if the endpoint isn't rate limited.
+    pub fn from_headers(headers: &reqwest::header::HeaderMap) -> Option<RateLimit> {
+        let header = |name: &str| -> Option<i64> { headers.get(name)?.to_str().ok()?.parse().ok() };
+
+        Some(RateLimit {
+            limit: header("x-ratelimit-limit")?.max(0) as u64,
+            remaining: header("x-ratelimit-remaining")?.max(0) as u64,
+            reset: header("x-ratelimit-reset")?,
+        })
+    }
+
+    /// Milliseconds until the budget is replenished, 0 if it already was
+    fn millis_until_reset(&self, now: i64) -> u64 {
+        (self.reset - now).max(0) as u64
+    }
+}
+
+/// Tracks the rate-limit budget of each endpoint
+#[derive(Default)]
+pub struct RateLimiter {
+    limits: Mutex<HashMap<String, RateLimit>>,
+}
+
+impl RateLimiter {
+    pub fn get(&self, endpoint: &str) -> Option<RateLimit> {
+        self.limits.lock().unwrap().get(endpoint).cloned()
+    }
+
+    pub fn update(&self, endpoint: &str, limit: RateLimit) {
+        self.limits
+            .lock()
+            .unwrap()
+            .insert(endpoint.to_string(), limit);
+    }
+
+    /// Waits until the endpoint has budget left and reserves one request of it
+    /// Requests made while the budget is exhausted are effectively queued here
+    pub async fn acquire(&self, endpoint: &str) {
+        loop {
+            let wait_ms = {
+                let mut limits = self.limits.lock().unwrap();
+                let now = chrono::Utc::now().timestamp_millis();
+                match limits.get_mut(endpoint) {
+                    Some(limit) if limit.millis_until_reset(now) > 0 => {
+                        if limit.remaining > 0 {
+                            limit.remaining -= 1;
+                            return;
+                        }
+                        limit.millis_until_reset(now)
+                    }
+                    _ => return,
+                }
+            };
+
+            log::debug!("{} is rate limited, waiting {}ms", endpoint, wait_ms);
+            tokio::time::sleep(Duration::from_millis(wait_ms + jitter_ms(wait_ms))).await;
+        }
+    }
+
+    /// How long to wait before retrying a request that was answered with 429
+    pub fn retry_delay(&self, endpoint: &str, attempt: u32) -> Duration {
+        let now = chrono::Utc::now().timestamp_millis();
+        match self.get(endpoint) {
+            Some(limit) if limit.millis_until_reset(now) > 0 => {
+                let wait_ms = limit.millis_until_reset(now);
+                Duration::from_millis(wait_ms + jitter_ms(wait_ms))
+            }
+            _ => backoff_delay(attempt),
+        }
+    }
+}
+
+/// The endpoint's path, which is what the server limits on, without leading slash or query
+/// For example "api/v1/channels.history". Parameters in the path, like rooms.upload's room ID,
+/// are left out, and file routes share a single key.
+pub fn key(endpoint: &str) -> &str {
+    let path = endpoint.trim_start_matches('/');
+    let path = path.split('?').next().unwrap_or(path);
+    let segments = if path.starts_with("api/") { 3 } else { 1 };
+    match path.match_indices('/').nth(segments - 1) {
+        Some((end, _)) => &path[..end],
+        None => path,
+    }
+}
+
+/// Exponential backoff with jitter, for the nth retry (starting at 0)
+pub fn backoff_delay(attempt: u32) -> Duration {
+    let ms = BASE_BACKOFF_MS
+        .saturating_mul(1 << attempt.min(16))
+        .min(MAX_BACKOFF_MS);
+    Duration::from_millis(ms + jitter_ms(ms))
+}
+
+/// Up to 25% of extra delay, so that clients don't retry in lockstep
+fn jitter_ms(ms: u64) -> u64 {
+    rand::thread_rng().gen_range(0..=ms / 4)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limit_from_headers() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        assert_eq!(RateLimit::from_headers(&headers), None);
+
+        headers.insert("X-RateLimit-Limit", "10".parse().unwrap());
+        headers.insert("X-RateLimit-Remaining", "3".parse().unwrap());
+        headers.insert("X-RateLimit-Reset", "1700000000000".parse().unwrap());
+        assert_eq!(
+            RateLimit::from_headers(&headers),
+            Some(RateLimit {
+                limit: 10,
+                remaining: 3,
+                reset: 1700000000000
+            })
+        );
+    }
+
+    #[test]
+    fn test_key() {
+        assert_eq!(
+            key("/api/v1/channels.history?roomId=GENERAL&offset=50"),
+            "api/v1/channels.history"
+        );
+        assert_eq!(key("api/info"), "api/info");
+        assert_eq!(key("api/v1/rooms.upload/GENERAL"), "api/v1/rooms.upload");
+        assert_eq!(key("file-upload/f1/log.txt"), "file-upload");
+    }
+
+    #[test]
+    fn test_backoff_delay() {
+        assert!(backoff_delay(0) >= Duration::from_millis(BASE_BACKOFF_MS));
+        assert!(backoff_delay(1) >= Duration::from_millis(2 * BASE_BACKOFF_MS));
+        assert!(backoff_delay(30) >= Duration::from_millis(MAX_BACKOFF_MS));
+        assert!(backoff_delay(30) <= Duration::from_millis(MAX_BACKOFF_MS * 5 / 4 + 1));
+    }
+
+    #[tokio::test]
+    async fn test_acquire_reserves_budget() {
+        let limiter = RateLimiter::default();
+        let reset = chrono::Utc::now().timestamp_millis() + 60_000;
+        limiter.update(
+            "api/v1/rooms.get",
+            RateLimit {
+                limit: 2,
+                remaining: 2,
+                reset,
+            },
+        );
+
+        limiter.acquire("api/v1/rooms.get").await;
+        limiter.acquire("api/v1/rooms.get").await;
+        assert_eq!(limiter.get("api/v1/rooms.get").unwrap().remaining, 0);
+
+        // Unknown endpoints aren't limited
+        limiter.acquire("api/v1/info").await;
+    }
+}
@@ -0,0 +1,557 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: Sergio Martins
+
+//! The realtime API: DDP over a websocket, see RocketChat::realtime()
+//!
+//! RocketChat::follow_realtime() keeps a connection open, announces new messages through
+//! RocketChat::note_message(), applies room changes through RocketChat::apply_room_change(), and
+//! turns changed reactions and statuses into events.
+
+use crate::{Event, Message, RocketChat, Room, Subscription, UserStatus};
+use futures::{SinkExt, StreamExt};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    time::Duration,
+};
+use tokio_tungstenite::tungstenite;
+
+type Socket =
+    tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+/// Returns the websocket URL for a server URL, for example wss://open.rocket.chat/websocket
+pub fn websocket_url(url: &str) -> String {
+    let url = url.trim_end_matches('/');
+    let url = match url.split_once("://") {
+        Some(("https", rest)) => format!("wss://{}", rest),
+        Some((_, rest)) => format!("ws://{}", rest),
+        None => format!("wss://{}", url),
+    };
+    format!("{}/websocket", url)
+}
+
+/// A logged-in realtime connection
+pub struct Realtime {
+    socket: Socket,
+    next_id: u64,
+    /// What arrived while waiting for a reply, for next_message()
+    pending: VecDeque<serde_json::Value>,
+}
+
+impl Realtime {
+    /// Connects and logs in with `auth_token`
+    pub(crate) async fn connect(url: &str, auth_token: &str) -> Result<Realtime, String> {
+        let url = websocket_url(url);
+        let (socket, _) = tokio_tungstenite::connect_async(&url)
+            .await
+            .map_err(|e| format!("couldn't connect to {}: {}", url, e))?;
+        let mut realtime = Realtime {
+            socket,
+            next_id: 0,
+            pending: VecDeque::new(),
+        };
+
+        realtime
+            .send(serde_json::json!({ "msg": "connect", "version": "1", "support": ["1"] }))
+            .await?;
+        loop {
+            match realtime
+                .receive()
+                .await?
+                .as_ref()
+                .map(|m| m["msg"].as_str())
+            {
+                Some(Some("connected")) => break,
+                Some(Some("failed")) => {
+                    return Err(String::from("the server refused DDP version 1"))
+                }
+                Some(_) => {}
+                None => return Err(String::from("connection closed while connecting")),
+            }
+        }
+
+        realtime
+            .call("login", serde_json::json!([{ "resume": auth_token }]))
+            .await?;
+        Ok(realtime)
+    }
+
+    async fn send(&mut self, message: serde_json::Value) -> Result<(), String> {
+        self.socket
+            .send(tungstenite::Message::Text(message.to_string()))
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    /// Returns the next DDP message, answering pings along the way. None once closed.
+    async fn receive(&mut self) -> Result<Option<serde_json::Value>, String> {
+        while let Some(frame) = self.socket.next().await {
+            let text = match frame.map_err(|e| e.to_string())? {
+                tungstenite::Message::Text(text) => text,
+                tungstenite::Message::Close(_) => return Ok(None),
+                _ => continue,
+            };
+            let message: serde_json::Value = serde_json::from_str(&text)
+                .map_err(|e| format!("invalid realtime message: {}", e))?;
+            if message["msg"].as_str() == Some("ping") {
+                self.send(serde_json::json!({ "msg": "pong" })).await?;
+                continue;
+            }
+            return Ok(Some(message));
+        }
+        Ok(None)
+    }
+
+    fn next_id(&mut self) -> String {
+        self.next_id += 1;
+        self.next_id.to_string()
+    }
+
+    /// Calls a method and waits for its result
+    async fn call(&mut self, method: &str, params: serde_json::Value) -> Result<(), String> {
+        let id = self.next_id();
+        self.send(
+            serde_json::json!({ "msg": "method", "id": id, "method": method, "params": params }),
+        )
+        .await?;
+        loop {
+            let message = self
+                .receive()
+                .await?
+                .ok_or(format!("connection closed during {}", method))?;
+            if message["msg"].as_str() == Some("result") && message["id"].as_str() == Some(&id) {
+                return match message.get("error") {
+                    Some(error) => Err(format!(
+                        "{} failed: {}",
+                        method,
+                        error["reason"].as_str().unwrap_or("unknown error")
+                    )),
+                    None => Ok(()),
+                };
+            }
+            self.pending.push_back(message);
+        }
+    }
+
+    /// Subscribes to a stream and waits until the server is ready to send it
+    async fn subscribe(&mut self, name: &str, params: serde_json::Value) -> Result<(), String> {
+        let id = self.next_id();
+        self.send(serde_json::json!({ "msg": "sub", "id": id, "name": name, "params": params }))
+            .await?;
+        loop {
+            let message = self
+                .receive()
+                .await?
+                .ok_or("connection closed while subscribing")?;
+            match message["msg"].as_str() {
+                Some("ready")
+                    if message["subs"]
+                        .as_array()
+                        .is_some_and(|subs| subs.contains(&serde_json::json!(id))) =>
+                {
+                    return Ok(())
+                }
+                Some("nosub") if message["id"].as_str() == Some(&id) => {
+                    return Err(format!("couldn't subscribe to {} {}", name, params))
+                }
+                _ => self.pending.push_back(message),
+            }
+        }
+    }
+
+    /// Asks for the messages posted in a room from now on, see next_message()
+    pub async fn subscribe_room_messages(&mut self, room_id: &str) -> Result<(), String> {
+        self.subscribe("stream-room-messages", serde_json::json!([room_id, false]))
+            .await
+    }
+
+    /// Asks for the messages posted in every room we're in, including the ones joined later
+    pub async fn subscribe_my_messages(&mut self) -> Result<(), String> {
+        self.subscribe_room_messages("__my_messages__").await
+    }
+
+    /// Asks for the rooms we join, leave, or that change, see next_update()
+    pub async fn subscribe_room_changes(&mut self, user_id: &str) -> Result<(), String> {
+        for event in ["subscriptions-changed", "rooms-changed"] {
+            self.subscribe(
+                "stream-notify-user",
+                serde_json::json!([format!("{}/{}", user_id, event), false]),
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Asks for everyone's status changes, see next_update()
+    pub async fn subscribe_presence(&mut self) -> Result<(), String> {
+        self.subscribe(
+            "stream-notify-logged",
+            serde_json::json!(["user-status", false]),
+        )
+        .await
+    }
+
+    /// Waits for the next message from a subscribed room
+    /// Edits and reactions come through here too, with the same ID. None once disconnected.
+    pub async fn next_message(&mut self) -> Result<Option<Message>, String> {
+        loop {
+            match self.next_update().await? {
+                Some(Update::Message(message)) => return Ok(Some(message)),
+                Some(_) => {}
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// Waits for the next thing any subscription reports. None once disconnected.
+    pub async fn next_update(&mut self) -> Result<Option<Update>, String> {
+        loop {
+            let message = match self.pending.pop_front() {
+                Some(message) => message,
+                None => match self.receive().await? {
+                    Some(message) => message,
+                    None => return Ok(None),
+                },
+            };
+            if let Some(update) = Update::from_json(&message)? {
+                return Ok(Some(update));
+            }
+        }
+    }
+}
+
+/// Something a subscription reported, see Realtime::next_update()
+#[derive(Clone, Debug)]
+pub enum Update {
+    /// A new message, or a new version of one: edited, reacted to, and so on
+    Message(Message),
+    Room(RoomChange),
+    /// Someone went online, away, and so on
+    Presence {
+        user_id: String,
+        status: UserStatus,
+    },
+}
+
+impl Update {
+    /// Parses a DDP message, None for the ones that aren't updates
+    fn from_json(message: &serde_json::Value) -> Result<Option<Update>, String> {
+        if message["msg"].as_str() != Some("changed") {
+            return Ok(None);
+        }
+
+        let fields = &message["fields"];
+        match message["collection"].as_str() {
+            Some("stream-room-messages") => {
+                Message::from_json(&fields["args"][0]).map(|m| Some(Update::Message(m)))
+            }
+            Some("stream-notify-user") => {
+                let event = fields["eventName"].as_str().unwrap_or_default();
+                // A room we can't make sense of isn't worth the connection
+                match RoomChange::from_json(event, &fields["args"]) {
+                    Ok(change) => Ok(change.map(Update::Room)),
+                    Err(e) => {
+                        log::warn!("realtime: ignoring {}: {}", event, e);
+                        Ok(None)
+                    }
+                }
+            }
+            Some("stream-notify-logged") if fields["eventName"] == "user-status" => {
+                // [user ID, username, status, status message]
+                let args = &fields["args"][0];
+                Ok(args[0].as_str().map(|user_id| Update::Presence {
+                    user_id: String::from(user_id),
+                    status: UserStatus::from_code(args[2].as_u64().unwrap_or(0)),
+                }))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+/// A change to the rooms we're in, see RocketChat::apply_room_change()
+#[derive(Clone, Debug)]
+pub enum RoomChange {
+    /// We joined a room or were added to one, or our subscription to it changed
+    Subscribed(Subscription),
+    /// A room we're in was renamed, got a new message, and so on
+    Updated(Room),
+    /// We left the room or were removed from it, or it was deleted, by ID
+    Left(String),
+}
+
+impl RoomChange {
+    /// Parses the arguments of a stream-notify-user event, None for other events
+    fn from_json(event: &str, args: &serde_json::Value) -> Result<Option<RoomChange>, String> {
+        let removed = args[0].as_str() == Some("removed");
+        let data = &args[1];
+        let change = match event.rsplit('/').next() {
+            Some("subscriptions-changed") if removed => RoomChange::Left(String::from(
+                data["rid"].as_str().ok_or("subscription rid is missing")?,
+            )),
+            Some("subscriptions-changed") => RoomChange::Subscribed(Subscription::from_json(data)?),
+            Some("rooms-changed") if removed => RoomChange::Left(String::from(
+                data["_id"].as_str().ok_or("room _id is missing")?,
+            )),
+            Some("rooms-changed") => RoomChange::Updated(Room::from_json(data)?),
+            _ => return Ok(None),
+        };
+        Ok(Some(change))
+    }
+}
+
+/// How long the reactions to a message are remembered, to tell what changed
+const REACTIONS_KEPT: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How often the reactions to old messages are forgotten
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// What we know about recent messages, across reconnections, see RocketChat::follow_realtime()
+pub(crate) struct Follower<'a> {
+    rc: &'a RocketChat,
+    /// Messages older than this are old news: from before we started, or pruned since
+    since: i64,
+    /// Reactions by message ID, with the message's timestamp to prune them by
+    reactions: HashMap<String, (i64, BTreeMap<String, Vec<String>>)>,
+}
+
+impl Follower<'_> {
+    pub(crate) fn new(rc: &RocketChat) -> Follower<'_> {
+        Follower {
+            rc,
+            since: chrono::Utc::now().timestamp(),
+            reactions: HashMap::new(),
+        }
+    }
+
+    /// Follows until logged out, reconnecting after `reconnect_delay`
+    pub(crate) async fn run(&mut self, reconnect_delay: Duration) {
+        let mut catch_up = false;
+        while self.rc.is_logged_in() {
+            if let Err(e) = self.follow(catch_up).await {
+                log::warn!("realtime: lost the connection: {}", e);
+            }
+            catch_up = true;
+            tokio::time::sleep(reconnect_delay).await;
+        }
+    }
+
+    /// Catches up with the rooms we were added to while disconnected
+    async fn refresh_rooms(&self) -> Result<(), String> {
+        let known = self.rc.rooms();
+        self.rc.list_rooms().await?;
+        for (id, room) in self.rc.rooms().iter() {
+            if !known.contains_key(id) {
+                self.rc.emit(Event::RoomAdded(room.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Forgets the messages older than REACTIONS_KEPT
+    fn prune(&mut self) {
+        let oldest = chrono::Utc::now().timestamp() - REACTIONS_KEPT.as_secs() as i64;
+        self.since = self.since.max(oldest);
+        let since = self.since;
+        self.reactions
+            .retain(|_, (timestamp, _)| *timestamp >= since);
+    }
+
+    /// Announces a new message, or what changed in the reactions to one already seen
+    fn note(&mut self, message: Message) {
+        let previous = self.reactions.insert(
+            message.id.clone(),
+            (message.timestamp, message.reactions.clone()),
+        );
+        match previous {
+            Some((_, previous)) => {
+                let username = self.rc.get_username();
+                for (emoji, reactor, added) in reaction_changes(&previous, &message.reactions) {
+                    // Our own reactions were announced by react() already
+                    if reactor != username {
+                        self.rc.emit(Event::ReactionChanged {
+                            message_id: message.id.clone(),
+                            emoji,
+                            username: reactor,
+                            added,
+                        });
+                    }
+                }
+            }
+            // Edits and reactions to old messages don't make them new
+            None if message.timestamp >= self.since => {
+                self.rc.note_message(&message);
+            }
+            None => {}
+        }
+    }
+
+    /// Follows our rooms and everyone's presence until the connection drops
+    /// After a reconnection, `catch_up` fetches the rooms again, as changes were missed
+    async fn follow(&mut self, catch_up: bool) -> Result<(), String> {
+        let mut realtime = self.rc.realtime().await?;
+        realtime.subscribe_my_messages().await?;
+        realtime
+            .subscribe_room_changes(&self.rc.get_user_id())
+            .await?;
+        realtime.subscribe_presence().await?;
+        if catch_up {
+            self.refresh_rooms().await?;
+        }
+
+        let mut prune = tokio::time::interval(PRUNE_INTERVAL);
+        loop {
+            let update = tokio::select! {
+                _ = prune.tick() => {
+                    self.prune();
+                    continue;
+                }
+                update = realtime.next_update() => update?,
+            };
+            match update {
+                Some(Update::Message(message)) => self.note(message),
+                Some(Update::Room(change)) => {
+                    if let Err(e) = self.rc.apply_room_change(change).await {
+                        log::warn!("realtime: couldn't apply a room change: {}", e);
+                    }
+                }
+                Some(Update::Presence { user_id, status }) => {
+                    self.rc.emit(Event::PresenceChanged { user_id, status })
+                }
+                None => return Err(String::from("disconnected")),
+            }
+        }
+    }
+}
+
+/// Returns (emoji, username, added) for who reacted or took their reaction back, between two
+/// versions of a message
+fn reaction_changes(
+    before: &BTreeMap<String, Vec<String>>,
+    after: &BTreeMap<String, Vec<String>>,
+) -> Vec<(String, String, bool)> {
+    let mut changes = Vec::new();
+    let mut diff =
+        |from: &BTreeMap<String, Vec<String>>, to: &BTreeMap<String, Vec<String>>, added: bool| {
+            for (emoji, usernames) in from {
+                for username in usernames {
+                    if !to.get(emoji).is_some_and(|names| names.contains(username)) {
+                        changes.push((emoji.clone(), username.clone(), added));
+                    }
+                }
+            }
+        };
+    diff(after, before, true);
+    diff(before, after, false);
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_websocket_url() {
+        assert_eq!(
+            websocket_url("https://open.rocket.chat/"),
+            "wss://open.rocket.chat/websocket"
+        );
+        assert_eq!(
+            websocket_url("http://localhost:3000"),
+            "ws://localhost:3000/websocket"
+        );
+    }
+
+    #[test]
+    fn test_room_changes() {
+        let parse = |event: &str, args: serde_json::Value| RoomChange::from_json(event, &args);
+
+        let joined = parse(
+            "u1/subscriptions-changed",
+            serde_json::json!(["inserted", { "rid": "GENERAL", "unread": 2 }]),
+        );
+        match joined {
+            Ok(Some(RoomChange::Subscribed(subscription))) => {
+                assert_eq!(subscription.room_id, "GENERAL");
+                assert_eq!(subscription.unread, 2);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let renamed = parse(
+            "u1/rooms-changed",
+            serde_json::json!(["updated", {
+                "_id": "GENERAL", "t": "c", "name": "general-chat",
+                "lm": { "$date": 1652799323276_i64 }
+            }]),
+        );
+        match renamed {
+            Ok(Some(RoomChange::Updated(Room::Channel(channel)))) => {
+                assert_eq!(channel.name, "general-chat");
+                assert_eq!(channel.last_message_timestamp, Some(1652799323));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let left = parse(
+            "u1/subscriptions-changed",
+            serde_json::json!(["removed", { "_id": "s1", "rid": "GENERAL" }]),
+        );
+        assert!(matches!(left, Ok(Some(RoomChange::Left(id))) if id == "GENERAL"));
+
+        assert!(matches!(
+            parse("u1/notification", serde_json::json!([{}])),
+            Ok(None)
+        ));
+        assert!(parse("u1/rooms-changed", serde_json::json!(["updated", {}])).is_err());
+    }
+
+    #[test]
+    fn test_presence() {
+        let update = Update::from_json(&serde_json::json!({
+            "msg": "changed",
+            "collection": "stream-notify-logged",
+            "id": "id",
+            "fields": {
+                "eventName": "user-status",
+                "args": [["u2", "bob", 2, "lunch"]]
+            }
+        }));
+        match update {
+            Ok(Some(Update::Presence { user_id, status })) => {
+                assert_eq!(user_id, "u2");
+                assert_eq!(status, UserStatus::Away);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let ready = serde_json::json!({ "msg": "ready", "subs": ["1"] });
+        assert!(matches!(Update::from_json(&ready), Ok(None)));
+    }
+
+    #[test]
+    fn test_reaction_changes() {
+        let reactions = |pairs: &[(&str, &[&str])]| -> BTreeMap<String, Vec<String>> {
+            pairs
+                .iter()
+                .map(|(emoji, names)| {
+                    (
+                        String::from(*emoji),
+                        names.iter().map(|n| String::from(*n)).collect(),
+                    )
+                })
+                .collect()
+        };
+        let before = reactions(&[(":tada:", &["alice", "bob"]), (":eyes:", &["carol"])]);
+        let after = reactions(&[(":tada:", &["alice"]), (":+1:", &["carol"])]);
+
+        assert_eq!(
+            reaction_changes(&before, &after),
+            vec![
+                (String::from(":+1:"), String::from("carol"), true),
+                (String::from(":eyes:"), String::from("carol"), false),
+                (String::from(":tada:"), String::from("bob"), false),
+            ]
+        );
+        assert!(reaction_changes(&after, &after).is_empty());
+    }
+}
@@ -0,0 +1,72 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: Sergio Martins
+
+/// Keys and headers whose values must never end up in logs, compared case-insensitively
+const SECRETS: &[&str] = &["authtoken", "password", "resume", "x-auth-token"];
+
+const REDACTED: &str = "<redacted>";
+
+fn is_secret(key: &str) -> bool {
+    SECRETS
+        .iter()
+        .any(|secret| key.eq_ignore_ascii_case(secret))
+}
+
+/// Returns a copy of `json` with secrets replaced, at any depth
+pub fn json(json: &serde_json::Value) -> serde_json::Value {
+    match json {
+        serde_json::Value::Object(map) => map
+            .iter()
+            .map(|(key, value)| {
+                let value = if is_secret(key) {
+                    serde_json::json!(REDACTED)
+                } else {
+                    self::json(value)
+                };
+                (key.clone(), value)
+            })
+            .collect(),
+        serde_json::Value::Array(items) => items.iter().map(self::json).collect(),
+        other => other.clone(),
+    }
+}
+
+/// Returns the value to log for a header
+pub fn header<'a>(name: &str, value: &'a str) -> &'a str {
+    if is_secret(name) {
+        REDACTED
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact_json() {
+        let body = serde_json::json!({
+            "status": "success",
+            "data": {
+                "userId": "u1",
+                "authToken": "secret",
+                "me": { "services": { "password": { "bcrypt": "hash" } } }
+            },
+            "list": [{ "resume": "secret" }]
+        });
+
+        let redacted = json(&body);
+        assert_eq!(redacted["data"]["userId"], "u1");
+        assert_eq!(redacted["data"]["authToken"], REDACTED);
+        assert_eq!(redacted["data"]["me"]["services"]["password"], REDACTED);
+        assert_eq!(redacted["list"][0]["resume"], REDACTED);
+        assert!(!redacted.to_string().contains("secret"));
+    }
+
+    #[test]
+    fn test_redact_header() {
+        assert_eq!(header("X-Auth-Token", "secret"), REDACTED);
+        assert_eq!(header("X-User-Id", "u1"), "u1");
+    }
+}
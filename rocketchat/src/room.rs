@@ -0,0 +1,164 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: Sergio Martins
+
+/// What kind of room this is
+/// Discussions and teams are backed by a regular channel or group, `private` tells which
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RoomType {
+    /// A public channel anyone can join
+    Channel,
+    /// An invite-only channel
+    PrivateGroup,
+    /// A direct conversation with someone
+    Direct,
+    /// A direct conversation with more than one person
+    MultiUserDirect,
+    /// A room spawned from another room, see `prid`
+    Discussion {
+        private: bool,
+    },
+    /// The main room of a team
+    TeamMain {
+        private: bool,
+    },
+    /// An omnichannel conversation with a visitor
+    Livechat,
+    Unknown,
+}
+
+/// The family of REST endpoints used to manage a room, for example `groups.members`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EndpointFamily {
+    Channels,
+    Groups,
+    Im,
+}
+
+impl EndpointFamily {
+    /// Returns the endpoint prefix, for example "groups"
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EndpointFamily::Channels => "channels",
+            EndpointFamily::Groups => "groups",
+            EndpointFamily::Im => "im",
+        }
+    }
+}
+
+impl RoomType {
+    /// Deduces the type from a room or subscription object, as returned by rooms.get
+    pub fn from_json(room: &serde_json::Value) -> RoomType {
+        let t = room["t"].as_str().unwrap_or("");
+        let private = t == "p";
+
+        if room["prid"].as_str().is_some() {
+            return RoomType::Discussion { private };
+        }
+
+        if room["teamMain"].as_bool().unwrap_or(false) {
+            return RoomType::TeamMain { private };
+        }
+
+        match t {
+            "c" => RoomType::Channel,
+            "p" => RoomType::PrivateGroup,
+            "d" => {
+                // Includes ourselves
+                let num_users = room["uids"]
+                    .as_array()
+                    .or(room["usernames"].as_array())
+                    .map(|users| users.len())
+                    .unwrap_or(0);
+                if num_users > 2 {
+                    RoomType::MultiUserDirect
+                } else {
+                    RoomType::Direct
+                }
+            }
+            "l" => RoomType::Livechat,
+            _ => RoomType::Unknown,
+        }
+    }
+
+    pub fn is_private(&self) -> bool {
+        match self {
+            RoomType::Channel => false,
+            RoomType::Discussion { private } | RoomType::TeamMain { private } => *private,
+            _ => true,
+        }
+    }
+
+    pub fn is_direct(&self) -> bool {
+        matches!(self, RoomType::Direct | RoomType::MultiUserDirect)
+    }
+
+    /// Returns which endpoints manage this room
+    /// None if the type is unknown, or for livechat rooms which have their own, differently shaped API
+    pub fn endpoint_family(&self) -> Option<EndpointFamily> {
+        match self {
+            RoomType::Channel => Some(EndpointFamily::Channels),
+            RoomType::PrivateGroup => Some(EndpointFamily::Groups),
+            RoomType::Direct | RoomType::MultiUserDirect => Some(EndpointFamily::Im),
+            RoomType::Discussion { private } | RoomType::TeamMain { private } => {
+                if *private {
+                    Some(EndpointFamily::Groups)
+                } else {
+                    Some(EndpointFamily::Channels)
+                }
+            }
+            RoomType::Livechat | RoomType::Unknown => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_room_type_from_json() {
+        assert_eq!(RoomType::from_json(&json!({"t": "c"})), RoomType::Channel);
+        assert_eq!(
+            RoomType::from_json(&json!({"t": "p"})),
+            RoomType::PrivateGroup
+        );
+        assert_eq!(
+            RoomType::from_json(&json!({"t": "d", "uids": ["a", "b"]})),
+            RoomType::Direct
+        );
+        assert_eq!(
+            RoomType::from_json(&json!({"t": "d", "usernames": ["a", "b", "c"]})),
+            RoomType::MultiUserDirect
+        );
+        assert_eq!(
+            RoomType::from_json(&json!({"t": "p", "prid": "parent"})),
+            RoomType::Discussion { private: true }
+        );
+        assert_eq!(
+            RoomType::from_json(&json!({"t": "c", "teamMain": true})),
+            RoomType::TeamMain { private: false }
+        );
+        assert_eq!(RoomType::from_json(&json!({"t": "l"})), RoomType::Livechat);
+        assert_eq!(RoomType::from_json(&json!({"t": "v"})), RoomType::Unknown);
+    }
+
+    #[test]
+    fn test_endpoint_family() {
+        assert_eq!(
+            RoomType::PrivateGroup.endpoint_family(),
+            Some(EndpointFamily::Groups)
+        );
+        assert_eq!(
+            RoomType::Discussion { private: false }.endpoint_family(),
+            Some(EndpointFamily::Channels)
+        );
+        assert_eq!(
+            RoomType::MultiUserDirect.endpoint_family(),
+            Some(EndpointFamily::Im)
+        );
+        assert_eq!(RoomType::Unknown.endpoint_family(), None);
+        assert_eq!(RoomType::Livechat.endpoint_family(), None);
+        assert_eq!(EndpointFamily::Groups.as_str(), "groups");
+    }
+}
@@ -0,0 +1,171 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: Sergio Martins
+
+/// Oldest server version we're tested against
+pub const MIN_SUPPORTED_VERSION: (u32, u32) = (6, 0);
+
+/// The public settings we care about, see `settings.public`
+pub const PUBLIC_SETTINGS: &[&str] = &[
+    "Message_MaxAllowedSize",
+    "FileUpload_Enabled",
+    "FileUpload_MaxFileSize",
+    "Accounts_ShowFormLogin",
+    "Accounts_TwoFactorAuthentication_Enabled",
+    "Accounts_RegistrationForm",
+    "LDAP_Enable",
+    "Accounts_OAuth_Apple",
+    "Accounts_OAuth_Facebook",
+    "Accounts_OAuth_Github",
+    "Accounts_OAuth_Gitlab",
+    "Accounts_OAuth_Google",
+    "Accounts_OAuth_Linkedin",
+    "Accounts_OAuth_Meteor",
+    "Accounts_OAuth_Twitter",
+];
+
+/// What the server is and what it supports
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ServerInfo {
+    pub version: String,
+    /// Commit or build date. Only sent to users allowed to see it.
+    pub build: Option<String>,
+    pub max_message_length: Option<u64>,
+    pub file_upload_enabled: bool,
+    /// In bytes. None means unlimited.
+    pub file_upload_max_size: Option<u64>,
+    pub login_methods: LoginMethods,
+    /// Whether users can turn on two-factor authentication. It may still be optional for them.
+    pub two_factor_available: bool,
+    /// Whether anyone can create an account
+    pub registration_open: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LoginMethods {
+    /// Username and password form
+    pub password: bool,
+    pub ldap: bool,
+    /// Enabled OAuth providers, for example "google" or "github"
+    pub oauth: Vec<String>,
+}
+
+impl LoginMethods {
+    pub fn only_oauth(&self) -> bool {
+        !self.password && !self.ldap && !self.oauth.is_empty()
+    }
+}
+
+impl ServerInfo {
+    /// Builds from the `api/info` and `settings.public` responses
+    pub fn from_json(info: &serde_json::Value, settings: &serde_json::Value) -> ServerInfo {
+        let setting = |id: &str| -> &serde_json::Value {
+            settings["settings"]
+                .as_array()
+                .and_then(|settings| settings.iter().find(|s| s["_id"].as_str() == Some(id)))
+                .map(|s| &s["value"])
+                .unwrap_or(&serde_json::Value::Null)
+        };
+
+        let build = info["info"]["commit"]["hash"]
+            .as_str()
+            .or(info["info"]["build"]["date"].as_str())
+            .map(String::from);
+
+        let oauth = PUBLIC_SETTINGS
+            .iter()
+            .filter_map(|id| id.strip_prefix("Accounts_OAuth_"))
+            .filter(|provider| {
+                setting(&format!("Accounts_OAuth_{}", provider))
+                    .as_bool()
+                    .unwrap_or(false)
+            })
+            .map(|provider| provider.to_lowercase())
+            .collect();
+
+        ServerInfo {
+            version: String::from(info["version"].as_str().unwrap_or_default()),
+            build,
+            max_message_length: setting("Message_MaxAllowedSize").as_u64(),
+            file_upload_enabled: setting("FileUpload_Enabled").as_bool().unwrap_or(false),
+            file_upload_max_size: setting("FileUpload_MaxFileSize")
+                .as_i64()
+                .filter(|size| *size >= 0)
+                .map(|size| size as u64),
+            login_methods: LoginMethods {
+                // Servers that don't tell are assumed to have the regular form
+                password: setting("Accounts_ShowFormLogin").as_bool().unwrap_or(true),
+                ldap: setting("LDAP_Enable").as_bool().unwrap_or(false),
+                oauth,
+            },
+            two_factor_available: setting("Accounts_TwoFactorAuthentication_Enabled")
+                .as_bool()
+                .unwrap_or(false),
+            registration_open: setting("Accounts_RegistrationForm").as_str() == Some("Public"),
+        }
+    }
+
+    /// Returns the major and minor version, if the version string is valid
+    pub fn version_numbers(&self) -> Option<(u32, u32)> {
+        let mut parts = self.version.split('.');
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next()?.parse().ok()?;
+        Some((major, minor))
+    }
+
+    pub fn is_supported(&self) -> bool {
+        self.version_numbers()
+            .map(|version| version >= MIN_SUPPORTED_VERSION)
+            .unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_server_info_from_json() {
+        let info = serde_json::json!({
+            "version": "6.5.2",
+            "success": true
+        });
+        let settings = serde_json::json!({
+            "settings": [
+                { "_id": "Message_MaxAllowedSize", "value": 5000 },
+                { "_id": "FileUpload_Enabled", "value": true },
+                { "_id": "FileUpload_MaxFileSize", "value": -1 },
+                { "_id": "Accounts_ShowFormLogin", "value": false },
+                { "_id": "Accounts_OAuth_Google", "value": true },
+                { "_id": "Accounts_OAuth_Github", "value": false },
+                { "_id": "Accounts_RegistrationForm", "value": "Disabled" }
+            ],
+            "success": true
+        });
+
+        let server_info = ServerInfo::from_json(&info, &settings);
+        assert_eq!(server_info.version, "6.5.2");
+        assert_eq!(server_info.build, None);
+        assert_eq!(server_info.max_message_length, Some(5000));
+        assert!(server_info.file_upload_enabled);
+        assert_eq!(server_info.file_upload_max_size, None);
+        assert_eq!(server_info.login_methods.oauth, vec!["google"]);
+        assert!(server_info.login_methods.only_oauth());
+        assert!(!server_info.registration_open);
+        assert!(server_info.is_supported());
+    }
+
+    #[test]
+    fn test_is_supported() {
+        let mut server_info = ServerInfo {
+            version: String::from("5.4.10"),
+            ..Default::default()
+        };
+        assert!(!server_info.is_supported());
+
+        server_info.version = String::from("garbage");
+        assert!(!server_info.is_supported());
+
+        server_info.version = String::from("7.0.0");
+        assert!(server_info.is_supported());
+    }
+}
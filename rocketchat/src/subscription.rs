@@ -0,0 +1,186 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: Sergio Martins
+
+/// When to be notified about new messages
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NotificationLevel {
+    /// Whatever the user's account preferences say
+    #[default]
+    Default,
+    All,
+    Mentions,
+    Nothing,
+}
+
+impl NotificationLevel {
+    pub fn parse(s: &str) -> NotificationLevel {
+        match s {
+            "all" => NotificationLevel::All,
+            "mentions" => NotificationLevel::Mentions,
+            "nothing" => NotificationLevel::Nothing,
+            _ => NotificationLevel::Default,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NotificationLevel::Default => "default",
+            NotificationLevel::All => "all",
+            NotificationLevel::Mentions => "mentions",
+            NotificationLevel::Nothing => "nothing",
+        }
+    }
+}
+
+/// Our relationship with a room: unread counts and preferences
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Subscription {
+    pub room_id: String,
+    pub favorite: bool,
+    /// False if the room was hidden from the sidebar
+    pub open: bool,
+    /// Whether there's something new
+    pub alert: bool,
+    pub unread: u64,
+    pub user_mentions: u64,
+    pub group_mentions: u64,
+    /// Notifications are turned off entirely
+    pub muted: bool,
+    pub desktop_notifications: NotificationLevel,
+    pub mobile_notifications: NotificationLevel,
+    pub email_notifications: NotificationLevel,
+    /// When to mark the room as unread
+    pub unread_alert: NotificationLevel,
+}
+
+impl Subscription {
+    /// Parses a subscription, as returned by subscriptions.get
+    pub fn from_json(s: &serde_json::Value) -> Result<Subscription, String> {
+        let level = |key: &str| NotificationLevel::parse(s[key].as_str().unwrap_or_default());
+
+        Ok(Subscription {
+            room_id: String::from(s["rid"].as_str().ok_or("subscription rid is missing")?),
+            favorite: s["f"].as_bool().unwrap_or(false),
+            open: s["open"].as_bool().unwrap_or(true),
+            alert: s["alert"].as_bool().unwrap_or(false),
+            unread: s["unread"].as_u64().unwrap_or(0),
+            user_mentions: s["userMentions"].as_u64().unwrap_or(0),
+            group_mentions: s["groupMentions"].as_u64().unwrap_or(0),
+            muted: s["disableNotifications"].as_bool().unwrap_or(false),
+            desktop_notifications: level("desktopNotifications"),
+            mobile_notifications: level("mobilePushNotifications"),
+            email_notifications: level("emailNotifications"),
+            unread_alert: level("unreadAlert"),
+        })
+    }
+}
+
+/// Notification settings to change, None leaves the setting alone
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NotificationPreferences {
+    pub muted: Option<bool>,
+    pub desktop: Option<NotificationLevel>,
+    pub mobile: Option<NotificationLevel>,
+    pub email: Option<NotificationLevel>,
+    pub unread_alert: Option<NotificationLevel>,
+}
+
+impl NotificationPreferences {
+    /// The `notifications` object expected by rooms.saveNotification
+    /// The server wants strings, even for booleans
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut json = serde_json::json!({});
+        if let Some(muted) = self.muted {
+            json["disableNotifications"] = serde_json::json!(if muted { "1" } else { "0" });
+        }
+        let levels = [
+            ("desktopNotifications", self.desktop),
+            ("mobilePushNotifications", self.mobile),
+            ("emailNotifications", self.email),
+            ("unreadAlert", self.unread_alert),
+        ];
+        for (key, level) in levels {
+            if let Some(level) = level {
+                json[key] = serde_json::json!(level.as_str());
+            }
+        }
+        json
+    }
+
+    /// Updates a subscription we already have, once the server accepted the change
+    pub fn apply(&self, subscription: &mut Subscription) {
+        if let Some(muted) = self.muted {
+            subscription.muted = muted;
+        }
+        if let Some(level) = self.desktop {
+            subscription.desktop_notifications = level;
+        }
+        if let Some(level) = self.mobile {
+            subscription.mobile_notifications = level;
+        }
+        if let Some(level) = self.email {
+            subscription.email_notifications = level;
+        }
+        if let Some(level) = self.unread_alert {
+            subscription.unread_alert = level;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subscription_from_json() {
+        let json = serde_json::json!({
+            "_id": "sub1",
+            "rid": "GENERAL",
+            "t": "c",
+            "f": true,
+            "open": true,
+            "alert": true,
+            "unread": 3,
+            "userMentions": 1,
+            "disableNotifications": true,
+            "desktopNotifications": "mentions"
+        });
+
+        let subscription = Subscription::from_json(&json).unwrap();
+        assert_eq!(subscription.room_id, "GENERAL");
+        assert!(subscription.favorite);
+        assert_eq!(subscription.unread, 3);
+        assert_eq!(subscription.user_mentions, 1);
+        assert!(subscription.muted);
+        assert_eq!(
+            subscription.desktop_notifications,
+            NotificationLevel::Mentions
+        );
+        assert_eq!(
+            subscription.mobile_notifications,
+            NotificationLevel::Default
+        );
+    }
+
+    #[test]
+    fn test_notification_preferences() {
+        let preferences = NotificationPreferences {
+            muted: Some(true),
+            email: Some(NotificationLevel::Nothing),
+            ..Default::default()
+        };
+        assert_eq!(
+            preferences.to_json(),
+            serde_json::json!({ "disableNotifications": "1", "emailNotifications": "nothing" })
+        );
+
+        let mut subscription = Subscription::default();
+        preferences.apply(&mut subscription);
+        assert!(subscription.muted);
+        assert_eq!(subscription.email_notifications, NotificationLevel::Nothing);
+        assert_eq!(
+            subscription.desktop_notifications,
+            NotificationLevel::Default
+        );
+    }
+}
@@ -0,0 +1,113 @@
+// SPDX-License-Identifier: MIT
+// SPDX-FileCopyrightText: Sergio Martins
+
+/// What initial_sync() fetches, all at the same time
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SyncStep {
+    ServerInfo,
+    JoinedChannels,
+    Rooms,
+    Subscriptions,
+    Profile,
+    CustomEmojis,
+}
+
+impl SyncStep {
+    pub const ALL: [SyncStep; 6] = [
+        SyncStep::ServerInfo,
+        SyncStep::JoinedChannels,
+        SyncStep::Rooms,
+        SyncStep::Subscriptions,
+        SyncStep::Profile,
+        SyncStep::CustomEmojis,
+    ];
+
+    /// Whether the sync is pointless without this step
+    pub fn is_fatal(&self) -> bool {
+        // Custom emojis are a nicety, some servers don't even let users list them
+        *self != SyncStep::CustomEmojis
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StepState {
+    Pending,
+    Running,
+    Done,
+    Failed(String),
+}
+
+/// Where initial_sync() is at, built from the SyncProgress events
+#[derive(Clone, Debug, PartialEq)]
+pub struct SyncProgress {
+    steps: Vec<(SyncStep, StepState)>,
+}
+
+impl Default for SyncProgress {
+    fn default() -> Self {
+        Self {
+            steps: SyncStep::ALL
+                .iter()
+                .map(|step| (*step, StepState::Pending))
+                .collect(),
+        }
+    }
+}
+
+impl SyncProgress {
+    pub fn update(&mut self, step: SyncStep, state: StepState) {
+        if let Some(entry) = self.steps.iter_mut().find(|(s, _)| *s == step) {
+            entry.1 = state;
+        }
+    }
+
+    pub fn state(&self, step: SyncStep) -> &StepState {
+        &self
+            .steps
+            .iter()
+            .find(|(s, _)| *s == step)
+            .expect("every step is tracked")
+            .1
+    }
+
+    /// Number of steps that finished, successfully or not
+    pub fn finished(&self) -> usize {
+        self.steps
+            .iter()
+            .filter(|(_, state)| matches!(state, StepState::Done | StepState::Failed(_)))
+            .count()
+    }
+
+    pub fn total(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.finished() == self.total()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sync_progress() {
+        let mut progress = SyncProgress::default();
+        assert_eq!(progress.finished(), 0);
+        assert_eq!(progress.total(), SyncStep::ALL.len());
+
+        progress.update(SyncStep::Rooms, StepState::Running);
+        progress.update(SyncStep::Profile, StepState::Done);
+        progress.update(
+            SyncStep::CustomEmojis,
+            StepState::Failed(String::from("forbidden")),
+        );
+        assert_eq!(progress.state(SyncStep::Rooms), &StepState::Running);
+        assert_eq!(progress.finished(), 2);
+        assert!(!progress.is_complete());
+
+        assert!(SyncStep::Rooms.is_fatal());
+        assert!(!SyncStep::CustomEmojis.is_fatal());
+    }
+}